@@ -0,0 +1,133 @@
+use std::{hash::Hasher, io, path::PathBuf};
+
+use tokio::sync::mpsc::Sender;
+
+use crate::{write_on_dir_diff_with_options, DiffOptions, DirDiffStats, WriteOutcome};
+
+/// The outcome of one file of [`write_on_dir_diff_async_with_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct DiffEvent {
+    /// The path relative to the destination root.
+    pub path: PathBuf,
+    /// What happened to the destination file.
+    pub outcome: WriteOutcome,
+}
+
+/// [`write_on_dir_diff`](crate::write_on_dir_diff) run on the blocking
+/// thread pool of the tokio runtime.
+pub async fn write_on_dir_diff_async<H: Hasher + Default + 'static>(
+    from: impl Into<PathBuf>,
+    to: impl Into<PathBuf>,
+) -> io::Result<DirDiffStats> {
+    write_on_dir_diff_async_with_options::<H>(from, to, DiffOptions::new).await
+}
+
+/// [`write_on_dir_diff_async`] sending an event over `events` for each file
+/// as it goes.
+///
+/// The diff waits while the channel is full, so a slow consumer such as a UI
+/// task slows the sync down instead of piling events up. Once the receiver
+/// is dropped the diff keeps going without sending.
+pub async fn write_on_dir_diff_async_with_events<H: Hasher + Default + 'static>(
+    from: impl Into<PathBuf>,
+    to: impl Into<PathBuf>,
+    events: Sender<DiffEvent>,
+) -> io::Result<DirDiffStats> {
+    write_on_dir_diff_async_with_options::<H>(from, to, move || {
+        DiffOptions::new().on_write(move |path, _, outcome| {
+            let path = path.to_path_buf();
+            let _ = events.blocking_send(DiffEvent { path, outcome });
+        })
+    })
+    .await
+}
+
+/// [`write_on_dir_diff_async`] with the options returned by `options`,
+/// called on the blocking thread the diff runs on.
+pub async fn write_on_dir_diff_async_with_options<H: Hasher + Default + 'static>(
+    from: impl Into<PathBuf>,
+    to: impl Into<PathBuf>,
+    options: impl FnOnce() -> DiffOptions<'static> + Send + 'static,
+) -> io::Result<DirDiffStats> {
+    let (from, to) = (from.into(), to.into());
+    tokio::task::spawn_blocking(move || {
+        write_on_dir_diff_with_options::<H>(from, to, &mut options())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use std::fs;
+    use tempfile::tempdir;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_dir_diff_async_events() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        fs::write(from_dir.path().join("sub/b.txt"), "b")?;
+        let to_dir = tempdir()?;
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let (stats, mut events) = runtime.block_on(async {
+            // A single slot, the diff has to wait for each event to be read.
+            let (sender, mut receiver) = mpsc::channel(1);
+            let diff = tokio::spawn(write_on_dir_diff_async_with_events::<FxHasher>(
+                from_dir.path().to_path_buf(),
+                to_dir.path().to_path_buf(),
+                sender,
+            ));
+            let mut events = Vec::new();
+            while let Some(event) = receiver.recv().await {
+                events.push(event);
+            }
+            (diff.await.unwrap(), events)
+        });
+
+        assert_eq!(stats?.created, 2);
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            events,
+            [
+                DiffEvent {
+                    path: "a.txt".into(),
+                    outcome: WriteOutcome::Created
+                },
+                DiffEvent {
+                    path: "sub/b.txt".into(),
+                    outcome: WriteOutcome::Created
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_async_with_options() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        fs::write(from_dir.path().join("b.txt"), "bb")?;
+        let to_dir = tempdir()?;
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let stats = runtime.block_on(write_on_dir_diff_async_with_options::<FxHasher>(
+            from_dir.path().to_path_buf(),
+            to_dir.path().to_path_buf(),
+            || DiffOptions::new().min_size(2),
+        ))?;
+
+        assert_eq!(stats.created, 1);
+        assert!(!to_dir.path().join("a.txt").exists());
+        assert_eq!(fs::read_to_string(to_dir.path().join("b.txt"))?, "bb");
+
+        Ok(())
+    }
+}
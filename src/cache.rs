@@ -0,0 +1,150 @@
+use std::{
+    collections::HashMap,
+    fs,
+    hash::Hasher,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    ensure_dest_read, error::Context, hash_file, hash_reader, write_bytes, DiffOptions, Operation,
+    Side, SkipReason, WriteOutcome,
+};
+
+/// Destination hashes remembered between calls of
+/// [`write_on_bytes_diff_cached`], keyed by path, along with the size and
+/// modification time the file had when hashed.
+pub type HashCache = HashMap<PathBuf, (u64, SystemTime, u64)>;
+
+/// [`write_on_bytes_diff`](crate::write_on_bytes_diff) reusing the hash of
+/// `to` from `cache` while its size and modification time are unchanged,
+/// for long running processes writing the same files over and over.
+///
+/// The cache is updated after each call. A change keeping both the size and
+/// the modification time, possible on filesystems with coarse timestamps,
+/// goes unnoticed.
+pub fn write_on_bytes_diff_cached<H: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+    cache: &mut HashCache,
+) -> io::Result<WriteOutcome> {
+    write_on_bytes_diff_cached_with_options::<H>(from, to, cache, &mut DiffOptions::default())
+}
+
+/// [`write_on_bytes_diff_cached`] with `options`, e.g. to fail on a
+/// destination that can't be read through
+/// [`DiffOptions::dest_unreadable`].
+pub fn write_on_bytes_diff_cached_with_options<H: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+    cache: &mut HashCache,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<WriteOutcome> {
+    let to = to.as_ref();
+    let from_hash = hash_reader::<H>(Cursor::new(from))?;
+    let outcome = match stamp(to)? {
+        Some((size, mtime)) => {
+            let to_hash = match cache.get(to) {
+                Some(&(cached_size, cached_mtime, hash))
+                    if cached_size == size && cached_mtime == mtime =>
+                {
+                    Ok(hash)
+                }
+                _ => hash_file::<H>(to, options).context(Operation::Read, Side::Destination, to),
+            };
+            ensure_dest_read(to, &to_hash, options)?;
+            if matches!(to_hash, Ok(to_hash) if to_hash == from_hash) {
+                cache.insert(to.to_path_buf(), (size, mtime, from_hash));
+                return Ok(WriteOutcome::Skipped(SkipReason::HashMatch));
+            }
+            WriteOutcome::Overwritten
+        }
+        None => WriteOutcome::Created,
+    };
+    cache.remove(to);
+    write_bytes(from, to, options)?;
+    if let Some((size, mtime)) = stamp(to)? {
+        cache.insert(to.to_path_buf(), (size, mtime, from_hash));
+    }
+    Ok(outcome)
+}
+
+/// The size and modification time of `path`, `None` if it doesn't exist.
+fn stamp(path: &Path) -> io::Result<Option<(u64, SystemTime)>> {
+    match fs::metadata(path).and_then(|metadata| Ok((metadata.len(), metadata.modified()?))) {
+        Ok(stamp) => Ok(Some(stamp)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(Operation::Metadata, Side::Destination, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_bytes_diff_cached() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("a.txt");
+        let mut cache = HashCache::new();
+
+        let outcome = write_on_bytes_diff_cached::<FxHasher>(b"hello", &to_path, &mut cache)?;
+        assert_eq!(outcome, WriteOutcome::Created);
+        let hash = hash_reader::<FxHasher>(&b"hello"[..])?;
+        assert_eq!(cache[&to_path].2, hash);
+
+        // A stale cached hash is trusted while size and mtime are unchanged.
+        let (size, mtime, _) = cache[&to_path];
+        cache.insert(
+            to_path.clone(),
+            (size, mtime, hash_reader::<FxHasher>(&b"world"[..])?),
+        );
+        let outcome = write_on_bytes_diff_cached::<FxHasher>(b"world", &to_path, &mut cache)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::HashMatch));
+        assert_eq!(fs::read(&to_path)?, b"hello");
+
+        // And dropped once the file changes.
+        fs::File::options()
+            .write(true)
+            .open(&to_path)?
+            .set_modified(mtime + Duration::from_secs(1))?;
+        let outcome = write_on_bytes_diff_cached::<FxHasher>(b"world", &to_path, &mut cache)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, b"world");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_cached_dest_unreadable() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("a.txt");
+        fs::create_dir(&to_path)?;
+        let mut cache = HashCache::new();
+
+        // The default policy overwrites, failing to write over a directory
+        // rather than to read it.
+        let err =
+            write_on_bytes_diff_cached::<FxHasher>(b"hello", &to_path, &mut cache).unwrap_err();
+        assert_eq!(
+            crate::DiffError::from_io(&err).unwrap().operation,
+            Operation::Write
+        );
+
+        let options = &mut DiffOptions::new().dest_unreadable(crate::DestUnreadable::Error);
+        let err = write_on_bytes_diff_cached_with_options::<FxHasher>(
+            b"hello", &to_path, &mut cache, options,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            crate::WodError::from_io(&err),
+            Some(crate::WodError::DestinationUnreadable { .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    instrument::{count, Io},
+    WodError,
+};
+
+/// How a directory diff treats names differing only by case, which end up
+/// as one file on case-insensitive filesystems and as two on the others.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseCollisions {
+    /// Treat names as distinct, like the filesystems of most Unix systems.
+    #[default]
+    Allow,
+    /// Fail with [`WodError::CaseCollision`] listing the colliding names.
+    Error,
+    /// Keep the first source name in byte order and skip the others, and
+    /// write to an existing destination with a different casing instead of
+    /// creating a second file next to it.
+    Canonical,
+}
+
+fn fold(name: &OsStr) -> String {
+    name.to_string_lossy().to_lowercase()
+}
+
+/// Pair the entry names of the source directory `from` with the destination
+/// names to write them to, `None` for entries to skip.
+pub(crate) fn resolve(
+    from: &Path,
+    to: &Path,
+    mut names: Vec<OsString>,
+    policy: CaseCollisions,
+) -> io::Result<Vec<(OsString, Option<OsString>)>> {
+    if policy == CaseCollisions::Allow {
+        return Ok(names
+            .into_iter()
+            .map(|name| (name.clone(), Some(name)))
+            .collect());
+    }
+    names.sort();
+    let mut existing = HashMap::new();
+    count(Io::ReadDir);
+    match fs::read_dir(to) {
+        Ok(entries) => {
+            for entry in entries {
+                let name = entry?.file_name();
+                existing.insert(fold(&name), name);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut folded: HashMap<String, Vec<OsString>> = HashMap::new();
+    for name in &names {
+        folded.entry(fold(name)).or_default().push(name.clone());
+    }
+    if policy == CaseCollisions::Error {
+        let mut collisions: Vec<_> = folded
+            .into_iter()
+            .filter_map(|(key, mut group)| {
+                if let Some(dest) = existing.get(&key) {
+                    if !group.contains(dest) {
+                        group.push(dest.clone());
+                    }
+                }
+                (group.len() > 1).then_some(group)
+            })
+            .collect();
+        if let Some(names) = collisions.pop() {
+            return Err(WodError::CaseCollision {
+                dir: from.to_path_buf(),
+                names,
+            }
+            .into());
+        }
+        return Ok(names
+            .into_iter()
+            .map(|name| (name.clone(), Some(name)))
+            .collect());
+    }
+
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let key = fold(&name);
+            if folded[&key][0] != name {
+                return (name, None);
+            }
+            let dest = existing.get(&key).cloned().unwrap_or_else(|| name.clone());
+            (name, Some(dest))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn names(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_resolve_canonical() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("readme.txt"), "")?;
+
+        let resolved = resolve(
+            Path::new("from"),
+            to_dir.path(),
+            names(&["ReadMe.txt", "README.txt", "other"]),
+            CaseCollisions::Canonical,
+        )?;
+
+        assert_eq!(
+            resolved,
+            [
+                ("README.txt".into(), Some("readme.txt".into())),
+                ("ReadMe.txt".into(), None),
+                ("other".into(), Some("other".into())),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_error() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("readme.txt"), "")?;
+
+        let err = resolve(
+            Path::new("from"),
+            to_dir.path(),
+            names(&["ReadMe.txt", "other"]),
+            CaseCollisions::Error,
+        )
+        .unwrap_err();
+
+        let Some(WodError::CaseCollision { names, .. }) = WodError::from_io(&err) else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(names, &["ReadMe.txt", "readme.txt"]);
+
+        Ok(())
+    }
+}
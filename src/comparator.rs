@@ -0,0 +1,57 @@
+//! Comparators chosen per file from its content, see [`ComparatorRegistry`].
+
+use std::fmt;
+
+/// Decides whether two versions of a file are equal for a content type, e.g.
+/// PNG images decoding to the same pixels.
+pub trait Comparator {
+    /// Whether the destination content `to` is equivalent to the source
+    /// content `from`, so it needn't be written.
+    fn equivalent(&self, from: &[u8], to: &[u8]) -> bool;
+}
+
+impl<F: Fn(&[u8], &[u8]) -> bool> Comparator for F {
+    fn equivalent(&self, from: &[u8], to: &[u8]) -> bool {
+        self(from, to)
+    }
+}
+
+/// Looks at the start of the source content, such as its magic bytes, and
+/// returns the comparator for it if it's a type it knows.
+type Sniffer<'a> = Box<dyn Fn(&[u8]) -> Option<Box<dyn Comparator>> + 'a>;
+
+/// Comparators picked by sniffing the source content, set through
+/// [`DiffOptions::comparators`](crate::DiffOptions::comparators).
+///
+/// Sniffers are asked in the order they were registered and the first
+/// comparator returned is used. Files no sniffer recognizes are compared by
+/// hash as usual.
+#[derive(Default)]
+pub struct ComparatorRegistry<'a> {
+    sniffers: Vec<Sniffer<'a>>,
+}
+
+impl<'a> ComparatorRegistry<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `sniffer`, asked after the ones registered before it.
+    pub fn register(mut self, sniffer: impl Fn(&[u8]) -> Option<Box<dyn Comparator>> + 'a) -> Self {
+        self.sniffers.push(Box::new(sniffer));
+        self
+    }
+
+    /// The comparator of the first sniffer recognizing `from`.
+    pub(crate) fn sniff(&self, from: &[u8]) -> Option<Box<dyn Comparator>> {
+        self.sniffers.iter().find_map(|sniffer| sniffer(from))
+    }
+}
+
+impl fmt::Debug for ComparatorRegistry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ComparatorRegistry")
+            .field("sniffers", &self.sniffers.len())
+            .finish()
+    }
+}
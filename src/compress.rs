@@ -0,0 +1,60 @@
+#[cfg(feature = "flate2")]
+use std::io::{Read, Write};
+use std::{io, path::Path};
+
+/// The compression of a destination, chosen from its extension when
+/// [`DiffOptions::compressed_dest`](crate::DiffOptions::compressed_dest) is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Codec {
+    #[cfg(feature = "flate2")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Codec {
+    pub(crate) fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            #[cfg(feature = "flate2")]
+            "gz" => Some(Codec::Gzip),
+            #[cfg(feature = "zstd")]
+            "zst" => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "flate2", feature = "zstd")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn decode(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "flate2")]
+            Codec::Gzip => {
+                let mut decoded = Vec::new();
+                flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut decoded)?;
+                Ok(decoded)
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::decode_all(bytes),
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "flate2", feature = "zstd")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn encode(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "flate2")]
+            Codec::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::encode_all(bytes, 0),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::{io, path::Path};
+
+use crate::{DiffOptions, SkipReason, WodError};
+
+/// A destination about to be written while both sides changed since the
+/// baseline given by [`DiffOptions::baseline`](crate::DiffOptions::baseline).
+#[derive(Debug, Clone, Copy)]
+pub struct Conflict<'p> {
+    /// The source file, `None` for in-memory sources.
+    pub from: Option<&'p Path>,
+    pub to: &'p Path,
+    pub from_hash: u64,
+    pub to_hash: u64,
+    pub baseline_hash: u64,
+}
+
+/// How to resolve a [`Conflict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictAction {
+    /// Write the source over the destination.
+    Overwrite,
+    /// Keep the destination, reported as [`SkipReason::Conflict`].
+    Skip,
+    /// Stop with [`WodError::Aborted`].
+    Abort,
+}
+
+/// Returns why the write shouldn't happen, if the conflict callback says so.
+pub(crate) fn resolve(
+    from: Option<&Path>,
+    to: &Path,
+    from_hash: u64,
+    to_hash: Option<u64>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<Option<SkipReason>> {
+    let (Some(baseline), Some(on_conflict), Some(to_hash)) =
+        (&mut options.baseline, &mut options.on_conflict, to_hash)
+    else {
+        return Ok(None);
+    };
+    let Some(baseline_hash) = baseline(to) else {
+        return Ok(None);
+    };
+    if from_hash == baseline_hash || to_hash == baseline_hash {
+        return Ok(None);
+    }
+    let conflict = Conflict {
+        from,
+        to,
+        from_hash,
+        to_hash,
+        baseline_hash,
+    };
+    match on_conflict(&conflict) {
+        ConflictAction::Overwrite => Ok(None),
+        ConflictAction::Skip => Ok(Some(SkipReason::Conflict)),
+        ConflictAction::Abort => Err(WodError::Aborted {
+            path: to.to_path_buf(),
+        }
+        .into()),
+    }
+}
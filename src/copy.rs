@@ -0,0 +1,130 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use crate::{error::Context, Operation, Side};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reports the bytes copied so far to a single destination along with its
+/// final size, set through [`DiffOptions::on_progress`](crate::DiffOptions::on_progress).
+pub(crate) type OnProgress<'a> = Box<dyn FnMut(&Path, u64, u64) + 'a>;
+
+/// Copy `reader` into `writer` chunk by chunk, calling `on_chunk` with the
+/// total bytes copied after each of them.
+pub(crate) fn copy_chunked(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    from: Option<&Path>,
+    to: &Path,
+    mut on_chunk: impl FnMut(u64),
+) -> io::Result<u64> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context(Operation::Read, Side::Source, from.unwrap_or(to)),
+        };
+        writer
+            .write_all(&buf[..n])
+            .context(Operation::Write, Side::Destination, to)?;
+        copied += n as u64;
+        on_chunk(copied);
+    }
+    writer
+        .flush()
+        .context(Operation::Write, Side::Destination, to)?;
+    Ok(copied)
+}
+
+/// [`fs::copy`] reporting progress to `on_progress`.
+pub(crate) fn copy_file_with_progress(
+    from: &Path,
+    to: &Path,
+    on_progress: &mut OnProgress<'_>,
+) -> io::Result<u64> {
+    let mut reader = File::open(from).context(Operation::Read, Side::Source, from)?;
+    let metadata = reader
+        .metadata()
+        .context(Operation::Metadata, Side::Source, from)?;
+    let mut writer = File::create(to).context(Operation::Write, Side::Destination, to)?;
+    let total = metadata.len();
+    let copied = copy_chunked(&mut reader, &mut writer, Some(from), to, |copied| {
+        on_progress(to, copied, total)
+    })?;
+    fs::set_permissions(to, metadata.permissions()).context(
+        Operation::Write,
+        Side::Destination,
+        to,
+    )?;
+    Ok(copied)
+}
+
+/// [`fs::copy`] keeping the holes of sparse files, found with `SEEK_DATA` and
+/// `SEEK_HOLE`. Filesystems without hole tracking report the whole file as
+/// data, which degrades to a plain copy.
+#[cfg(target_os = "linux")]
+pub(crate) fn copy_file_sparse(from: &Path, to: &Path) -> io::Result<u64> {
+    use std::{
+        io::{Seek, SeekFrom},
+        os::unix::io::AsRawFd,
+    };
+
+    let mut reader = File::open(from).context(Operation::Read, Side::Source, from)?;
+    let metadata = reader
+        .metadata()
+        .context(Operation::Metadata, Side::Source, from)?;
+    let len = metadata.len();
+    let mut writer = File::create(to).context(Operation::Write, Side::Destination, to)?;
+    // Unwritten ranges of the destination are left as holes.
+    writer
+        .set_len(len)
+        .context(Operation::Write, Side::Destination, to)?;
+
+    let fd = reader.as_raw_fd();
+    let mut offset = 0;
+    while offset < len as libc::off_t {
+        // SAFETY: lseek is called on a file descriptor owned by `reader`.
+        let data = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+        if data < 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::ENXIO) {
+                // Only a hole is left.
+                break;
+            }
+            return Err(error).context(Operation::Read, Side::Source, from);
+        }
+        // SAFETY: as above.
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error()).context(Operation::Read, Side::Source, from);
+        }
+        reader
+            .seek(SeekFrom::Start(data as u64))
+            .context(Operation::Read, Side::Source, from)?;
+        writer.seek(SeekFrom::Start(data as u64)).context(
+            Operation::Write,
+            Side::Destination,
+            to,
+        )?;
+        copy_chunked(
+            &mut (&mut reader).take((hole - data) as u64),
+            &mut writer,
+            Some(from),
+            to,
+            |_| {},
+        )?;
+        offset = hole;
+    }
+    fs::set_permissions(to, metadata.permissions()).context(
+        Operation::Write,
+        Side::Destination,
+        to,
+    )?;
+    Ok(len)
+}
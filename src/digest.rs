@@ -0,0 +1,103 @@
+//! BLAKE3 digests of sources taken along the reads a diff does anyway, for
+//! [`DiffOptions::b3sums`](crate::DiffOptions::b3sums), only kept with the
+//! `blake3` feature so the calls compile to nothing otherwise.
+
+use std::io::{self, Read};
+#[cfg(feature = "blake3")]
+use std::path::Path;
+
+use crate::DiffOptions;
+#[cfg(feature = "blake3")]
+use crate::{
+    error::Context,
+    instrument::{count, Io},
+    Operation, Side,
+};
+
+/// The digest of a source being read, when asked for.
+pub(crate) struct SourceDigest {
+    #[cfg(feature = "blake3")]
+    hasher: Option<blake3::Hasher>,
+}
+
+impl SourceDigest {
+    pub(crate) fn new(options: &DiffOptions<'_>) -> Self {
+        #[cfg(feature = "blake3")]
+        return Self {
+            hasher: options.b3sums.then(blake3::Hasher::new),
+        };
+        #[cfg(not(feature = "blake3"))]
+        {
+            let _ = options;
+            Self {}
+        }
+    }
+
+    /// `reader` feeding what it reads into the digest.
+    pub(crate) fn reader<R: Read>(&mut self, reader: R) -> DigestReader<'_, R> {
+        DigestReader {
+            reader,
+            digest: self,
+        }
+    }
+
+    /// Keep the digest of everything read for the file being diffed.
+    pub(crate) fn finish(self, options: &mut DiffOptions<'_>) {
+        #[cfg(feature = "blake3")]
+        if let Some(hasher) = self.hasher {
+            options.b3sum = Some(*hasher.finalize().as_bytes());
+        }
+        #[cfg(not(feature = "blake3"))]
+        let _ = options;
+    }
+}
+
+pub(crate) struct DigestReader<'d, R> {
+    reader: R,
+    digest: &'d mut SourceDigest,
+}
+
+impl<R: Read> Read for DigestReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        #[cfg(feature = "blake3")]
+        if let Some(hasher) = &mut self.digest.hasher {
+            hasher.update(&buf[..n]);
+        }
+        #[cfg(not(feature = "blake3"))]
+        let _ = &self.digest;
+        Ok(n)
+    }
+}
+
+/// Whether digests are asked for, so that sources go through a reader of
+/// this crate rather than a copy left to the platform.
+pub(crate) fn enabled(options: &DiffOptions<'_>) -> bool {
+    #[cfg(feature = "blake3")]
+    return options.b3sums;
+    #[cfg(not(feature = "blake3"))]
+    {
+        let _ = options;
+        false
+    }
+}
+
+/// The digest of `from` kept by the diff, reading it only if the diff
+/// didn't, e.g. when it was skipped as the same inode.
+#[cfg(feature = "blake3")]
+pub(crate) fn take(from: &Path, options: &mut DiffOptions<'_>) -> io::Result<[u8; 32]> {
+    if let Some(digest) = options.b3sum.take() {
+        return Ok(digest);
+    }
+    count(Io::Open);
+    let file = options
+        .fs()
+        .open_read(from)
+        .context(Operation::Read, Side::Source, from)?;
+    count(Io::Read);
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_reader(file)
+        .context(Operation::Read, Side::Source, from)?;
+    Ok(*hasher.finalize().as_bytes())
+}
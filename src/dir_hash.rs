@@ -0,0 +1,101 @@
+use std::{
+    fs::{self, File},
+    hash::{BuildHasher, BuildHasherDefault, Hasher},
+    io::{self, BufReader, Write},
+    path::Path,
+};
+
+use crate::{error::Context, hash_reader, HashWriter, Operation, Side};
+
+/// One hash of a whole directory tree, covering the relative path of every
+/// entry, including empty directories, and the content of every file.
+///
+/// Two trees hashing the same hold the same files, so comparing the hashes
+/// of both sides of a sync tells whether anything differs. Entries are
+/// hashed in sorted order with `/` separated paths, the result doesn't depend
+/// on the listing order or the platform.
+pub fn hash_dir<H: Hasher + Default>(path: impl AsRef<Path>) -> io::Result<u64> {
+    let root = path.as_ref();
+    let mut entries = Vec::new();
+    collect::<H>(root, String::new(), &mut entries)?;
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut hash = HashWriter::new(BuildHasherDefault::<H>::default().build_hasher());
+    for (rel, file_hash) in entries {
+        hash.write_all(rel.as_bytes())?;
+        match file_hash {
+            Some(file_hash) => {
+                hash.write_all(b"\0f")?;
+                hash.write_all(&file_hash.to_le_bytes())?;
+            }
+            None => hash.write_all(b"\0d")?,
+        }
+    }
+    Ok(hash.finish())
+}
+
+/// Push the relative path of every entry under `dir`, with the content hash
+/// of files, `None` for directories.
+fn collect<H: Hasher + Default>(
+    dir: &Path,
+    prefix: String,
+    entries: &mut Vec<(String, Option<u64>)>,
+) -> io::Result<()> {
+    // Listed in full first, not to hold a handle per level of the tree.
+    let dir_entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .context(Operation::ReadDir, Side::Source, dir)?;
+    for entry in dir_entries {
+        let path = entry.path();
+        let rel = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if path.is_dir() {
+            entries.push((rel.clone(), None));
+            collect::<H>(&path, rel + "/", entries)?;
+        } else {
+            let file = File::open(&path).context(Operation::Read, Side::Source, &path)?;
+            let file_hash = hash_reader::<H>(BufReader::new(file)).context(
+                Operation::Read,
+                Side::Source,
+                &path,
+            )?;
+            entries.push((rel, Some(file_hash)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use tempfile::tempdir;
+
+    fn tree(root: &Path) -> io::Result<()> {
+        fs::create_dir_all(root.join("sub/empty"))?;
+        fs::write(root.join("a.txt"), "a")?;
+        fs::write(root.join("sub/b.txt"), "b")
+    }
+
+    #[test]
+    fn test_hash_dir() -> io::Result<()> {
+        let a = tempdir()?;
+        let b = tempdir()?;
+        tree(a.path())?;
+        tree(b.path())?;
+        let hash = hash_dir::<FxHasher>(a.path())?;
+        assert_eq!(hash, hash_dir::<FxHasher>(b.path())?);
+
+        fs::write(b.path().join("sub/b.txt"), "changed")?;
+        assert_ne!(hash, hash_dir::<FxHasher>(b.path())?);
+        fs::write(b.path().join("sub/b.txt"), "b")?;
+
+        fs::rename(b.path().join("a.txt"), b.path().join("c.txt"))?;
+        assert_ne!(hash, hash_dir::<FxHasher>(b.path())?);
+        fs::rename(b.path().join("c.txt"), b.path().join("a.txt"))?;
+
+        fs::remove_dir(b.path().join("sub/empty"))?;
+        assert_ne!(hash, hash_dir::<FxHasher>(b.path())?);
+
+        Ok(())
+    }
+}
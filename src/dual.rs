@@ -0,0 +1,127 @@
+use std::{
+    fs::File,
+    hash::Hasher,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    copy_file, error::Context, same_file, write_bytes, write_outcome, DiffOptions, HashWriter,
+    Operation, Side, SkipReason, WriteOutcome,
+};
+
+/// Feeds everything written into two hashers at once.
+struct DualWriter<H1: Hasher, H2: Hasher>(HashWriter<H1>, HashWriter<H2>);
+
+impl<H1: Hasher, H2: Hasher> Write for DualWriter<H1, H2> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Both hashes of `reader`, read once.
+fn hash_dual<H1: Hasher + Default, H2: Hasher + Default>(
+    mut reader: impl Read,
+) -> io::Result<(u64, u64)> {
+    let mut writer = DualWriter(
+        HashWriter::new(H1::default()),
+        HashWriter::new(H2::default()),
+    );
+    io::copy(&mut reader, &mut writer)?;
+    Ok((writer.0.finish(), writer.1.finish()))
+}
+
+fn hash_dual_file<H1: Hasher + Default, H2: Hasher + Default>(
+    path: &Path,
+) -> io::Result<(u64, u64)> {
+    hash_dual::<H1, H2>(BufReader::new(File::open(path)?))
+}
+
+/// [`write_on_file_diff`](crate::write_on_file_diff) treating both sides as
+/// equal only if two independent hashers agree on it, both computed in a
+/// single pass through each file.
+///
+/// A collision needs both 64-bit hashes to collide, for much less than a
+/// cryptographic digest costs.
+pub fn write_on_file_diff_dual<H1: Hasher + Default, H2: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> io::Result<WriteOutcome> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if same_file(from, to) {
+        return Ok(WriteOutcome::Skipped(SkipReason::SameInode));
+    }
+    let from_hash = hash_dual_file::<H1, H2>(from).context(Operation::Read, Side::Source, from)?;
+    let to_hash = hash_dual_file::<H1, H2>(to);
+    if matches!(to_hash, Ok(to_hash) if to_hash == from_hash) {
+        return Ok(WriteOutcome::Skipped(SkipReason::HashMatch));
+    }
+    copy_file(from, to, &mut DiffOptions::default())?;
+    Ok(write_outcome(&to_hash))
+}
+
+/// [`write_on_bytes_diff`](crate::write_on_bytes_diff) comparing with two
+/// hashers, see [`write_on_file_diff_dual`].
+pub fn write_on_bytes_diff_dual<H1: Hasher + Default, H2: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+) -> io::Result<WriteOutcome> {
+    let to = to.as_ref();
+    let from_hash = hash_dual::<H1, H2>(from)?;
+    let to_hash = hash_dual_file::<H1, H2>(to);
+    if matches!(to_hash, Ok(to_hash) if to_hash == from_hash) {
+        return Ok(WriteOutcome::Skipped(SkipReason::HashMatch));
+    }
+    write_bytes(from, to, &mut DiffOptions::default())?;
+    Ok(write_outcome(&to_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use std::{collections::hash_map::DefaultHasher, fs};
+    use tempfile::tempdir;
+
+    /// Hashes everything to the same value.
+    #[derive(Default)]
+    struct ConstHasher;
+
+    impl Hasher for ConstHasher {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    #[test]
+    fn test_dual_hashes_must_both_match() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("to.txt");
+
+        let outcome = write_on_bytes_diff_dual::<FxHasher, DefaultHasher>(b"a", &to_path)?;
+        assert_eq!(outcome, WriteOutcome::Created);
+        let outcome = write_on_bytes_diff_dual::<FxHasher, DefaultHasher>(b"a", &to_path)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::HashMatch));
+
+        // The constant hasher always collides, the other one tells them apart.
+        let outcome = write_on_bytes_diff_dual::<ConstHasher, FxHasher>(b"b", &to_path)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, b"b");
+
+        let from_path = to_dir.path().join("from.txt");
+        fs::write(&from_path, "c")?;
+        let outcome = write_on_file_diff_dual::<FxHasher, ConstHasher>(&from_path, &to_path)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, b"c");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Failures specific to this crate.
+///
+/// They are returned wrapped in an [`io::Error`]; use [`WodError::from_io`]
+/// to get them back.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WodError {
+    /// Source and destination hashes matched but their bytes differ.
+    HashCollision { path: PathBuf },
+    /// The conflict callback chose [`ConflictAction::Abort`](crate::ConflictAction::Abort).
+    Aborted { path: PathBuf },
+    /// Entries of `dir` differ only by case, see [`CaseCollisions::Error`](crate::CaseCollisions::Error).
+    CaseCollision { dir: PathBuf, names: Vec<OsString> },
+    /// The source of a single file diff is a directory, or a special file
+    /// such as a socket, when `is_dir` is false.
+    NotAFile { path: PathBuf, is_dir: bool },
+    /// Writing `path` needs more bytes than `available` on its filesystem,
+    /// see [`DiffOptions::check_free_space`](crate::DiffOptions::check_free_space).
+    InsufficientSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    /// The destinations at `paths` differ from their source, reported
+    /// instead of writing them with
+    /// [`DiffOptions::assert_unchanged`](crate::DiffOptions::assert_unchanged).
+    Changed { paths: Vec<PathBuf> },
+    /// The size or modification time of the source at `path` changed while
+    /// it was copied, see
+    /// [`DiffOptions::on_source_change`](crate::DiffOptions::on_source_change).
+    SourceChangedDuringCopy { path: PathBuf },
+    /// The destination of a directory diff is the source or one of its
+    /// parents. A destination inside the source is fine, it's left out of
+    /// the walk.
+    NestedDirs { from: PathBuf, to: PathBuf },
+    /// The existing destination at `path` couldn't be read to compare it,
+    /// see [`DiffOptions::dest_unreadable`](crate::DiffOptions::dest_unreadable).
+    DestinationUnreadable { path: PathBuf, error: io::Error },
+}
+
+impl WodError {
+    /// The `WodError` carried by `error`, if any.
+    pub fn from_io(error: &io::Error) -> Option<&WodError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            WodError::HashCollision { .. } => io::ErrorKind::InvalidData,
+            WodError::Aborted { .. } => io::ErrorKind::Other,
+            WodError::CaseCollision { .. } => io::ErrorKind::AlreadyExists,
+            WodError::NotAFile { is_dir: true, .. } => io::ErrorKind::IsADirectory,
+            WodError::NotAFile { is_dir: false, .. } => io::ErrorKind::InvalidInput,
+            WodError::InsufficientSpace { .. } => io::ErrorKind::StorageFull,
+            WodError::Changed { .. } => io::ErrorKind::Other,
+            WodError::SourceChangedDuringCopy { .. } => io::ErrorKind::Interrupted,
+            WodError::NestedDirs { .. } => io::ErrorKind::InvalidInput,
+            WodError::DestinationUnreadable { error, .. } => error.kind(),
+        }
+    }
+}
+
+impl fmt::Display for WodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WodError::HashCollision { path } => {
+                write!(
+                    f,
+                    "hash collision with different content at {}",
+                    path.display()
+                )
+            }
+            WodError::Aborted { path } => write!(f, "aborted on conflict at {}", path.display()),
+            WodError::CaseCollision { dir, names } => {
+                write!(f, "names differing only by case in {}:", dir.display())?;
+                for name in names {
+                    write!(f, " {}", name.to_string_lossy())?;
+                }
+                Ok(())
+            }
+            WodError::NotAFile { path, is_dir: true } => write!(
+                f,
+                "{} is a directory, use write_on_dir_diff to diff directories",
+                path.display()
+            ),
+            WodError::NotAFile {
+                path,
+                is_dir: false,
+            } => write!(f, "{} is not a regular file", path.display()),
+            WodError::InsufficientSpace {
+                path,
+                needed,
+                available,
+            } => write!(
+                f,
+                "not enough space to write {}: {needed} bytes needed, {available} available",
+                path.display()
+            ),
+            WodError::Changed { paths } => {
+                write!(f, "{} paths would change:", paths.len())?;
+                for path in paths {
+                    write!(f, " {}", path.display())?;
+                }
+                Ok(())
+            }
+            WodError::SourceChangedDuringCopy { path } => {
+                write!(f, "{} changed while being copied", path.display())
+            }
+            WodError::NestedDirs { from, to } => write!(
+                f,
+                "cannot diff {} into {} which contains it",
+                from.display(),
+                to.display()
+            ),
+            WodError::DestinationUnreadable { path, error } => {
+                write!(f, "cannot read destination {}: {error}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for WodError {}
+
+impl From<WodError> for io::Error {
+    fn from(error: WodError) -> Self {
+        io::Error::new(error.kind(), error)
+    }
+}
+
+/// The filesystem operation that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Operation {
+    Read,
+    Write,
+    Copy,
+    Metadata,
+    ReadDir,
+    CreateDir,
+    Remove,
+    /// Deciding whether a write is needed, such as on a hash collision.
+    Compare,
+}
+
+/// Which side of the diff an error happened on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Side {
+    Source,
+    Destination,
+}
+
+/// An IO error tagged with what failed and where.
+///
+/// Errors from the diff functions carry one, see [`DiffError::from_io`], and
+/// they are collected in [`DirDiffStats::errors`](crate::DirDiffStats::errors)
+/// by [`DiffOptions::continue_on_error`](crate::DiffOptions::continue_on_error).
+#[derive(Debug)]
+pub struct DiffError {
+    pub operation: Operation,
+    pub side: Side,
+    pub path: PathBuf,
+    /// The path on the other side of the file diffed when it failed, e.g.
+    /// the source a destination was being copied from.
+    pub counterpart: Option<PathBuf>,
+    pub error: io::Error,
+}
+
+impl DiffError {
+    /// The `DiffError` carried by `error`, if any.
+    pub fn from_io(error: &io::Error) -> Option<&DiffError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// Unwrap the `DiffError` carried by `error`, tagging it as a failed
+    /// comparison of `path` if it carries none.
+    pub(crate) fn from_io_or_compare(error: io::Error, path: &Path) -> DiffError {
+        if DiffError::from_io(&error).is_some() {
+            return *error
+                .into_inner()
+                .and_then(|inner| inner.downcast().ok())
+                .unwrap();
+        }
+        DiffError {
+            operation: Operation::Compare,
+            side: Side::Destination,
+            path: path.to_path_buf(),
+            counterpart: None,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = match self.side {
+            Side::Source => "source",
+            Side::Destination => "destination",
+        };
+        write!(
+            f,
+            "{:?} failed on {side} {}",
+            self.operation,
+            self.path.display()
+        )?;
+        if let Some(counterpart) = &self.counterpart {
+            let other_side = match self.side {
+                Side::Source => "destination",
+                Side::Destination => "source",
+            };
+            write!(f, " ({other_side} {})", counterpart.display())?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+impl Error for DiffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<DiffError> for io::Error {
+    fn from(error: DiffError) -> Self {
+        io::Error::new(error.error.kind(), error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for DiffError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("DiffError", 5)?;
+        state.serialize_field("operation", &self.operation)?;
+        state.serialize_field("side", &self.side)?;
+        state.serialize_field("path", &self.path)?;
+        state.serialize_field("counterpart", &self.counterpart)?;
+        state.serialize_field("error", &self.error.to_string())?;
+        state.end()
+    }
+}
+
+/// The error comes back as an [`io::ErrorKind::Other`] holding the message,
+/// its original kind isn't serialized.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DiffError {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Repr {
+            operation: Operation,
+            side: Side,
+            path: PathBuf,
+            #[serde(default)]
+            counterpart: Option<PathBuf>,
+            error: String,
+        }
+        let repr = Repr::deserialize(deserializer)?;
+        Ok(DiffError {
+            operation: repr.operation,
+            side: repr.side,
+            path: repr.path,
+            counterpart: repr.counterpart,
+            error: io::Error::other(repr.error),
+        })
+    }
+}
+
+/// Tag errors with the operation and path they come from.
+pub(crate) trait Context<T> {
+    fn context(self, operation: Operation, side: Side, path: &Path) -> io::Result<T>;
+
+    /// Add the path of the other side to the [`DiffError`] of a failed diff
+    /// from `from` to `to`, when it has none yet.
+    fn counterpart(self, from: Option<&Path>, to: &Path) -> io::Result<T>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context(self, operation: Operation, side: Side, path: &Path) -> io::Result<T> {
+        self.map_err(|error| {
+            if DiffError::from_io(&error).is_some() || WodError::from_io(&error).is_some() {
+                return error;
+            }
+            DiffError {
+                operation,
+                side,
+                path: path.to_path_buf(),
+                counterpart: None,
+                error,
+            }
+            .into()
+        })
+    }
+
+    fn counterpart(self, from: Option<&Path>, to: &Path) -> io::Result<T> {
+        self.map_err(|mut error| {
+            let diff_error = error
+                .get_mut()
+                .and_then(|inner| inner.downcast_mut::<DiffError>());
+            if let Some(diff_error) = diff_error.filter(|e| e.counterpart.is_none()) {
+                let other = match diff_error.side {
+                    Side::Source => Some(to),
+                    Side::Destination => from,
+                };
+                diff_error.counterpart = other
+                    .filter(|other| *other != diff_error.path)
+                    .map(Path::to_path_buf);
+            }
+            error
+        })
+    }
+}
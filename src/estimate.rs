@@ -0,0 +1,125 @@
+use std::{fs, hash::Hasher, io, path::Path};
+
+use crate::{plan_dir_diff, DiffAction, SavingsReport};
+
+/// Approximate how many files [`write_on_dir_diff`](crate::write_on_dir_diff)
+/// would write, without reading any content.
+///
+/// A source file counts as changed when its destination is missing, has a
+/// different size, or was modified before the source. This is cheap but only
+/// an estimate: content changed without changing size or mtime is missed,
+/// and a touched but identical file is counted.
+pub fn estimate_dir_changes(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<usize> {
+    let mut changes = 0;
+    // Listed in full first, not to hold a handle per level of the tree.
+    let entries = fs::read_dir(from)?.collect::<io::Result<Vec<_>>>()?;
+    for entry in entries {
+        let from_path = entry.path();
+        let to_path = to.as_ref().join(entry.file_name());
+        if from_path.is_dir() {
+            changes += estimate_dir_changes(&from_path, &to_path)?;
+        } else if is_probably_changed(&from_path, &to_path)? {
+            changes += 1;
+        }
+    }
+    Ok(changes)
+}
+
+/// Compare `from` with `to` like [`write_on_dir_diff`](crate::write_on_dir_diff)
+/// would, hashing files without writing anything, and report how many bytes
+/// it would write against copying every source file.
+pub fn estimate_write_savings<H: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> io::Result<SavingsReport> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    let mut report = SavingsReport::default();
+    for action in plan_dir_diff::<H>(from, to) {
+        let (changed, to_path) = match action? {
+            DiffAction::Create(path) | DiffAction::Overwrite(path) => (true, path),
+            DiffAction::Skip(path) => (false, path),
+            DiffAction::Delete(_) => continue,
+        };
+        let rel = to_path.strip_prefix(to).unwrap_or(&to_path);
+        let len = fs::metadata(from.join(rel))?.len();
+        report.total_source_bytes += len;
+        if changed {
+            report.bytes_that_would_change += len;
+        }
+    }
+    Ok(report)
+}
+
+fn is_probably_changed(from: &Path, to: &Path) -> io::Result<bool> {
+    let from_meta = fs::metadata(from)?;
+    let Ok(to_meta) = fs::metadata(to) else {
+        return Ok(true);
+    };
+    if from_meta.len() != to_meta.len() {
+        return Ok(true);
+    }
+    Ok(match (from_meta.modified(), to_meta.modified()) {
+        (Ok(from_mtime), Ok(to_mtime)) => from_mtime > to_mtime,
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_estimate_dir_changes() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        fs::write(from_dir.path().join("same.txt"), "same")?;
+        fs::write(to_dir.path().join("same.txt"), "same")?;
+        fs::write(from_dir.path().join("resized.txt"), "longer")?;
+        fs::write(to_dir.path().join("resized.txt"), "short")?;
+        fs::write(to_dir.path().join("stale.txt"), "old")?;
+        fs::write(from_dir.path().join("stale.txt"), "new")?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/missing.txt"), "missing")?;
+
+        // Make ordering explicit regardless of timestamp granularity.
+        let past = SystemTime::now() - Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(to_dir.path().join("stale.txt"))?
+            .set_modified(past)?;
+        fs::File::options()
+            .write(true)
+            .open(from_dir.path().join("same.txt"))?
+            .set_modified(past)?;
+
+        assert_eq!(estimate_dir_changes(from_dir.path(), to_dir.path())?, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_write_savings() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+        fs::write(from_dir.path().join("same.txt"), "same content")?;
+        fs::write(to_dir.path().join("same.txt"), "same content")?;
+        fs::write(from_dir.path().join("changed.txt"), "new")?;
+        fs::write(to_dir.path().join("changed.txt"), "old")?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/missing.txt"), "missing")?;
+        fs::write(to_dir.path().join("extra.txt"), "extra")?;
+
+        let report =
+            estimate_write_savings::<rustc_hash::FxHasher>(from_dir.path(), to_dir.path())?;
+
+        assert_eq!(report.total_source_bytes, 22);
+        assert_eq!(report.bytes_that_would_change, 10);
+        assert!((report.saved_percent() - 54.5).abs() < 0.1);
+        assert_eq!(fs::read(to_dir.path().join("changed.txt"))?, b"old");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use std::{io, path::Path};
+
+/// Copy the BSD file flags (`st_flags`, such as `UF_IMMUTABLE` or
+/// `UF_HIDDEN`) of `from` to `to`. Does nothing on platforms without them.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn copy_flags(from: &Path, to: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (from, to) = (c_path(from)?, c_path(to)?);
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: both paths are valid C strings and `stat` is only read once
+    // stat succeeded and initialized it.
+    unsafe {
+        if libc::stat(from.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let flags = stat.assume_init().st_flags;
+        if libc::chflags(to.as_ptr(), flags as _) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn copy_flags(_from: &Path, _to: &Path) -> io::Result<()> {
+    Ok(())
+}
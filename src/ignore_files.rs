@@ -0,0 +1,50 @@
+use std::{io, path::Path};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// The ignore files of every directory between the source root and the
+/// directory being walked, deepest last.
+#[derive(Default)]
+pub(crate) struct IgnoreStack(Vec<Gitignore>);
+
+impl IgnoreStack {
+    /// Load the ignore files of `dir`, returns whether any was found and
+    /// needs a matching [`IgnoreStack::pop`].
+    pub(crate) fn push(&mut self, dir: &Path) -> io::Result<bool> {
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+        for name in IGNORE_FILES {
+            let path = dir.join(name);
+            if path.is_file() {
+                if let Some(e) = builder.add(path) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                found = true;
+            }
+        }
+        if found {
+            let gitignore = builder
+                .build()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.0.push(gitignore);
+        }
+        Ok(found)
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.0.pop();
+    }
+
+    /// Deeper ignore files take precedence, like in git.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for gitignore in self.0.iter().rev() {
+            let matched = gitignore.matched(path, is_dir);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,83 @@
+//! IO operation counters, only kept with the `instrument` feature so the
+//! calls compile to nothing otherwise.
+
+#[cfg(feature = "instrument")]
+use std::cell::Cell;
+
+/// How many IO operations a directory diff performed, see
+/// [`DirDiffStats::io`](crate::DirDiffStats::io).
+///
+/// These count operations as issued by this crate rather than system calls:
+/// reading a whole file counts as one read however many chunks it takes.
+#[cfg(feature = "instrument")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IoCounters {
+    /// Files opened for reading or writing.
+    pub opens: u64,
+    /// Files read, in full or until their content differed.
+    pub reads: u64,
+    /// Files written or removed, and directories created.
+    pub writes: u64,
+    /// Metadata lookups, including existence checks.
+    pub metadata: u64,
+    /// Whole file copies left to the platform.
+    pub copies: u64,
+    /// Directory listings.
+    pub read_dirs: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Io {
+    Open,
+    Read,
+    Write,
+    Metadata,
+    Copy,
+    ReadDir,
+}
+
+#[cfg(feature = "instrument")]
+thread_local! {
+    static COUNTERS: Cell<IoCounters> = Cell::default();
+}
+
+/// Count one `io` operation on this thread.
+#[inline]
+pub(crate) fn count(io: Io) {
+    #[cfg(feature = "instrument")]
+    COUNTERS.with(|counters| {
+        let mut c = counters.get();
+        match io {
+            Io::Open => c.opens += 1,
+            Io::Read => c.reads += 1,
+            Io::Write => c.writes += 1,
+            Io::Metadata => c.metadata += 1,
+            Io::Copy => c.copies += 1,
+            Io::ReadDir => c.read_dirs += 1,
+        }
+        counters.set(c);
+    });
+    #[cfg(not(feature = "instrument"))]
+    let _ = io;
+}
+
+#[cfg(feature = "instrument")]
+impl IoCounters {
+    /// The operations counted on this thread so far.
+    pub(crate) fn current() -> Self {
+        COUNTERS.with(Cell::get)
+    }
+
+    /// The operations counted between `before` and `self`.
+    pub(crate) fn since(self, before: Self) -> Self {
+        Self {
+            opens: self.opens - before.opens,
+            reads: self.reads - before.reads,
+            writes: self.writes - before.writes,
+            metadata: self.metadata - before.metadata,
+            copies: self.copies - before.copies,
+            read_dirs: self.read_dirs - before.read_dirs,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{error::Context, Operation, Side};
+
+const HEADER: &str = "wod-journal";
+
+/// Destinations completed by an earlier run of the same session, see
+/// [`DiffOptions::journal`](crate::DiffOptions::journal).
+///
+/// The file starts with a `wod-journal <session>` line followed by one
+/// destination path per line, appended as they get done.
+pub(crate) struct Journal {
+    path: PathBuf,
+    done: HashSet<String>,
+    file: File,
+}
+
+impl Journal {
+    /// Open the journal at `path`, starting over unless it belongs to
+    /// `session`.
+    pub(crate) fn open(path: &Path, session: &str) -> io::Result<Self> {
+        let header = format!("{HEADER} {session}");
+        let done = match File::open(path) {
+            Ok(file) => {
+                let mut lines = BufReader::new(file).lines();
+                match lines.next().transpose()? {
+                    Some(first) if first == header => lines.collect::<io::Result<_>>()?,
+                    _ => HashSet::new(),
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        let file = if done.is_empty() {
+            let mut file = File::create(path)?;
+            writeln!(file, "{header}")?;
+            file
+        } else {
+            OpenOptions::new().append(true).open(path)?
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            done,
+            file,
+        })
+    }
+
+    pub(crate) fn contains(&self, to: &Path) -> bool {
+        self.done.contains(&*to.to_string_lossy())
+    }
+
+    pub(crate) fn record(&mut self, to: &Path) -> io::Result<()> {
+        writeln!(self.file, "{}", to.to_string_lossy()).context(
+            Operation::Write,
+            Side::Destination,
+            &self.path,
+        )
+    }
+
+    /// Remove the journal once the run completed.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path).context(Operation::Write, Side::Destination, &self.path)
+    }
+}
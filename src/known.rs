@@ -0,0 +1,74 @@
+use std::{
+    collections::HashSet,
+    hash::Hasher,
+    io::{self, Cursor},
+    path::Path,
+};
+
+use crate::{
+    error::Context, hash_file, hash_reader, write_on_bytes_diff_with_options,
+    write_on_file_diff_with_options, DiffOptions, Operation, Side, SkipReason, WriteOutcome,
+};
+
+/// [`write_on_bytes_diff`](crate::write_on_bytes_diff) skipping the write
+/// when the hash of `from` is in `known`, reported as
+/// [`SkipReason::KnownHash`], e.g. to populate a content-addressed store
+/// with content it doesn't hold anywhere yet.
+///
+/// The hashes in `known` must come from the same hasher.
+pub fn write_on_bytes_diff_unless_known<H: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+    known: &HashSet<u64>,
+) -> io::Result<WriteOutcome> {
+    if known.contains(&hash_reader::<H>(Cursor::new(from))?) {
+        return Ok(WriteOutcome::Skipped(SkipReason::KnownHash));
+    }
+    write_on_bytes_diff_with_options::<H>(from, to, &mut DiffOptions::default())
+}
+
+/// [`write_on_bytes_diff_unless_known`] copying the file `from`.
+pub fn write_on_file_diff_unless_known<H: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    known: &HashSet<u64>,
+) -> io::Result<WriteOutcome> {
+    let from = from.as_ref();
+    let options = &mut DiffOptions::default();
+    let from_hash = hash_file::<H>(from, options).context(Operation::Read, Side::Source, from)?;
+    if known.contains(&from_hash) {
+        return Ok(WriteOutcome::Skipped(SkipReason::KnownHash));
+    }
+    write_on_file_diff_with_options::<H>(from, to, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_bytes_diff_unless_known() -> io::Result<()> {
+        let store = tempdir()?;
+        let known = HashSet::from([hash_reader::<FxHasher>(&b"stored"[..])?]);
+
+        let to_path = store.path().join("copy");
+        let outcome = write_on_bytes_diff_unless_known::<FxHasher>(b"stored", &to_path, &known)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::KnownHash));
+        assert!(!to_path.exists());
+
+        let outcome = write_on_bytes_diff_unless_known::<FxHasher>(b"new", &to_path, &known)?;
+        assert_eq!(outcome, WriteOutcome::Created);
+        assert_eq!(fs::read(&to_path)?, b"new");
+
+        let from_path = store.path().join("from");
+        fs::write(&from_path, "stored")?;
+        let outcome = write_on_file_diff_unless_known::<FxHasher>(&from_path, &to_path, &known)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::KnownHash));
+        assert_eq!(fs::read(&to_path)?, b"new");
+
+        Ok(())
+    }
+}
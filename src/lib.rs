@@ -1,15 +1,165 @@
 use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
     fs::{self, File},
     hash::{BuildHasher, BuildHasherDefault, Hasher},
-    io::{self, BufReader, Cursor},
-    path::Path,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::atomic,
+    time::SystemTime,
 };
 
-struct HashWriter<T: Hasher>(T);
+#[cfg(feature = "tokio")]
+mod async_diff;
+mod cache;
+mod case;
+mod comparator;
+mod compress;
+mod conflict;
+mod copy;
+mod digest;
+mod dir_hash;
+mod dual;
+mod error;
+mod estimate;
+mod flags;
+#[cfg(feature = "ignore")]
+mod ignore_files;
+mod instrument;
+mod journal;
+mod known;
+mod limit;
+mod listing;
+mod memdir;
+#[cfg(feature = "blake3")]
+mod merkle;
+mod options;
+mod plan;
+mod retry;
+mod sidecar;
+mod similarity;
+mod sink;
+mod space;
+mod stats;
+#[cfg(feature = "blake3")]
+mod sums;
+#[cfg(feature = "tar")]
+mod tar_diff;
+mod text;
+mod timing;
+mod verify;
+mod vfs;
+#[cfg(feature = "watch")]
+mod watch;
+
+#[cfg(feature = "tokio")]
+pub use async_diff::{
+    write_on_dir_diff_async, write_on_dir_diff_async_with_events,
+    write_on_dir_diff_async_with_options, DiffEvent,
+};
+pub use cache::{write_on_bytes_diff_cached, write_on_bytes_diff_cached_with_options, HashCache};
+pub use case::CaseCollisions;
+pub use comparator::{Comparator, ComparatorRegistry};
+use compress::Codec;
+pub use conflict::{Conflict, ConflictAction};
+use digest::SourceDigest;
+pub use dir_hash::hash_dir;
+pub use dual::{write_on_bytes_diff_dual, write_on_file_diff_dual};
+use error::Context;
+pub use error::{DiffError, Operation, Side, WodError};
+pub use estimate::{estimate_dir_changes, estimate_write_savings};
+#[cfg(feature = "instrument")]
+pub use instrument::IoCounters;
+use instrument::{count, Io};
+pub use known::{write_on_bytes_diff_unless_known, write_on_file_diff_unless_known};
+pub use limit::FileLimit;
+pub use listing::{
+    write_on_listing_diff, write_on_listing_diff_with_options, EntryKind, ListedEntry,
+};
+pub use memdir::{write_on_memdir_diff, write_on_memdir_diff_with_options};
+#[cfg(feature = "blake3")]
+pub use merkle::{merkle_root, merkle_tree, MerkleNode};
+use options::Canonicalize;
+pub use options::{
+    Backup, BrokenSymlinks, CompareStrategy, DestSymlinks, DestUnreadable, DiffOptions, EntryOrder,
+    SampleSpec, SourceChange, VerifyOnMatch, WriteMode,
+};
+pub use plan::{plan_dir_diff, DiffAction};
+pub use retry::RetryPolicy;
+pub use sink::DiffSink;
+pub use stats::{
+    DirDiffStats, SavingsReport, SkipReason, UpsertReport, VerifyReport, WriteOutcome,
+};
+#[cfg(feature = "blake3")]
+pub use sums::write_b3sums;
+#[cfg(feature = "tar")]
+pub use tar_diff::{write_on_tar_diff, write_on_tar_diff_with_options};
+pub use text::{Bom, TextOptions};
+#[cfg(feature = "timing")]
+pub use timing::Timings;
+use timing::{timed, Phase};
+pub use verify::verify_against_manifest;
+pub use vfs::{
+    write_on_bytes_diff_in, write_on_dir_diff_in, write_on_file_diff_in, FileSystem, FsMetadata,
+    StdFs,
+};
+#[cfg(feature = "watch")]
+pub use watch::{watch_dir_diff, WatchHandle};
+
+/// An [`io::Write`] feeding everything written into a [`Hasher`], for
+/// hashing streams the same way this crate does.
+///
+/// Input is handed to the hasher in fixed size blocks, so the hash only
+/// depends on the bytes written and not on how they were split into writes.
+pub struct HashWriter<T: Hasher> {
+    hasher: T,
+    block: Vec<u8>,
+}
+
+const HASH_BLOCK_SIZE: usize = 8 * 1024;
+
+impl<T: Hasher> HashWriter<T> {
+    pub fn new(hasher: T) -> Self {
+        Self {
+            hasher,
+            block: Vec::with_capacity(HASH_BLOCK_SIZE),
+        }
+    }
+
+    /// The hash of everything written.
+    pub fn finish(self) -> u64 {
+        self.into_inner().finish()
+    }
+
+    /// The hasher, after it was fed everything written.
+    pub fn into_inner(mut self) -> T {
+        if !self.block.is_empty() {
+            self.hasher.write(&self.block);
+        }
+        self.hasher
+    }
+}
 
 impl<T: Hasher> io::Write for HashWriter<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf);
+        let mut rest = buf;
+        if !self.block.is_empty() {
+            let n = rest.len().min(HASH_BLOCK_SIZE - self.block.len());
+            self.block.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            if self.block.len() < HASH_BLOCK_SIZE {
+                return Ok(buf.len());
+            }
+            self.hasher.write(&self.block);
+            self.block.clear();
+        }
+        let mut blocks = rest.chunks_exact(HASH_BLOCK_SIZE);
+        for block in &mut blocks {
+            self.hasher.write(block);
+        }
+        self.block.extend_from_slice(blocks.remainder());
         Ok(buf.len())
     }
 
@@ -22,422 +172,4331 @@ impl<T: Hasher> io::Write for HashWriter<T> {
     }
 }
 
-pub fn write_on_file_diff<H: Hasher + Default>(
-    from: impl AsRef<Path>,
-    to: impl AsRef<Path>,
-) -> io::Result<()> {
-    let from = from.as_ref();
-    let to = to.as_ref();
+fn hash_reader<H: Hasher + Default>(mut reader: impl Read) -> io::Result<u64> {
     let build_hasher = BuildHasherDefault::<H>::default();
-    let from_hash = {
-        let mut from_hash = HashWriter(build_hasher.build_hasher());
-        io::copy(&mut BufReader::new(File::open(from)?), &mut from_hash)?;
-        from_hash.0.finish()
+    let mut hash = HashWriter::new(build_hasher.build_hasher());
+    timed(Phase::Hash, || io::copy(&mut reader, &mut hash))?;
+    Ok(hash.finish())
+}
+
+fn hash_file<H: Hasher + Default>(path: &Path, options: &mut DiffOptions<'_>) -> io::Result<u64> {
+    let fs = options.fs();
+    options.retry.run(|| {
+        count(Io::Open);
+        let file = fs.open_read(path)?;
+        count(Io::Read);
+        hash_reader::<H>(BufReader::new(file))
+    })
+}
+
+/// The content to be written, either an existing file or an in-memory buffer.
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    File(&'a Path),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Source<'a> {
+    /// The hash of the content, also taking its digest for
+    /// [`DiffOptions::b3sums`] in the same read.
+    fn hash<H: Hasher + Default>(self, options: &mut DiffOptions<'_>) -> io::Result<u64> {
+        let fs = options.fs();
+        let (hash, digest) = match self {
+            Source::File(path) => options
+                .retry
+                .run(|| {
+                    count(Io::Open);
+                    let file = fs.open_read(path)?;
+                    count(Io::Read);
+                    let mut digest = SourceDigest::new(options);
+                    let hash = hash_reader::<H>(digest.reader(BufReader::new(file)))?;
+                    Ok((hash, digest))
+                })
+                .context(Operation::Read, Side::Source, path)?,
+            Source::Bytes(bytes) => {
+                let mut digest = SourceDigest::new(options);
+                let hash = hash_reader::<H>(digest.reader(Cursor::new(bytes)))?;
+                (hash, digest)
+            }
+        };
+        digest.finish(options);
+        Ok(hash)
+    }
+
+    /// The size of the content, `None` if unknown.
+    fn len(self, options: &DiffOptions<'_>) -> Option<u64> {
+        match self {
+            Source::File(path) => {
+                count(Io::Metadata);
+                let metadata = timed(Phase::Metadata, || options.fs().metadata(path));
+                metadata.ok().map(|metadata| metadata.len)
+            }
+            Source::Bytes(bytes) => Some(bytes.len() as u64),
+        }
+    }
+
+    fn path(self) -> Option<&'a Path> {
+        match self {
+            Source::File(path) => Some(path),
+            Source::Bytes(_) => None,
+        }
+    }
+
+    fn reader<'b>(self, fs: &'b dyn FileSystem) -> io::Result<Box<dyn BufRead + 'b>>
+    where
+        'a: 'b,
+    {
+        Ok(match self {
+            Source::File(path) => {
+                count(Io::Open);
+                let file = fs
+                    .open_read(path)
+                    .context(Operation::Read, Side::Source, path)?;
+                count(Io::Read);
+                Box::new(BufReader::new(file))
+            }
+            Source::Bytes(bytes) => Box::new(bytes),
+        })
+    }
+
+    fn write_to(self, to: &Path, options: &mut DiffOptions<'_>) -> io::Result<()> {
+        match self {
+            Source::File(path) => copy_file(path, to, options).map(|_| ()),
+            Source::Bytes(bytes) => write_bytes(bytes, to, options),
+        }
+    }
+}
+
+/// Remove `to` if it's a symlink to be replaced rather than written through,
+/// or a file hardlinked by [`DiffOptions::dedup`] whose other links must keep
+/// their content.
+fn unlink_dest_link(to: &Path, options: &DiffOptions<'_>) -> io::Result<()> {
+    if options.dest_symlinks == DestSymlinks::Follow && !options.dedup {
+        return Ok(());
+    }
+    count(Io::Metadata);
+    match timed(Phase::Metadata, || fs::symlink_metadata(to)) {
+        Ok(metadata)
+            if (metadata.file_type().is_symlink()
+                && options.dest_symlinks == DestSymlinks::Replace)
+                || (options.dedup && is_shared(&metadata)) =>
+        {
+            count(Io::Write);
+            fs::remove_file(to).context(Operation::Write, Side::Destination, to)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Create the symlink `link` pointing to `target`, as a file link on
+/// Windows.
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(target, link);
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (target, link);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Whether the file has other hardlinks, only known on Unix.
+fn is_shared(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.is_file() && metadata.nlink() > 1
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+/// Fail with [`WodError::Changed`] instead of writing `to` when asked to
+/// [`DiffOptions::assert_unchanged`].
+fn ensure_writable(to: &Path, options: &DiffOptions<'_>) -> io::Result<()> {
+    if !options.assert_unchanged {
+        return Ok(());
+    }
+    let paths = vec![to.to_path_buf()];
+    Err(WodError::Changed { paths }.into())
+}
+
+/// Move the existing `to` aside as asked by [`DiffOptions::backup`], right
+/// before it gets overwritten.
+fn backup_dest(to: &Path, options: &DiffOptions<'_>) -> io::Result<()> {
+    let Some(backup) = &options.backup else {
+        return Ok(());
+    };
+    let backup_path = match backup {
+        Backup::Suffix(suffix) => {
+            let mut name = to.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        }
+        Backup::Dir(dir) => {
+            let rel = options
+                .dest_root
+                .as_deref()
+                .and_then(|root| to.strip_prefix(root).ok())
+                .or_else(|| to.file_name().map(Path::new))
+                .unwrap_or(to);
+            dir.join(rel)
+        }
     };
-    let to_hash = (|| -> Result<_, io::Error> {
-        let mut to_hash = HashWriter(build_hasher.build_hasher());
-        io::copy(&mut BufReader::new(File::open(to)?), &mut to_hash)?;
-        Ok(to_hash.0.finish())
-    })();
-    if to_hash.ok() != Some(from_hash) {
-        fs::copy(from, to)?;
+    if let Some(parent) = backup_path.parent() {
+        count(Io::Write);
+        fs::create_dir_all(parent).context(Operation::CreateDir, Side::Destination, parent)?;
     }
-    Ok(())
+    count(Io::Write);
+    if !options.preserve_inode && fs::rename(to, &backup_path).is_ok() {
+        return Ok(());
+    }
+    // Renaming fails across filesystems, e.g. to a backup directory on
+    // another disk, and would move the inode to keep away.
+    count(Io::Copy);
+    timed(Phase::Copy, || fs::copy(to, &backup_path))
+        .map(|_| ())
+        .context(Operation::Copy, Side::Destination, &backup_path)
 }
 
-pub fn write_on_bytes_diff<H: Hasher + Default>(
-    from: &[u8],
-    to: impl AsRef<Path>,
+/// Fail with [`WodError::InsufficientSpace`] if writing `len` bytes to `to`
+/// would fill its filesystem, when asked to check.
+fn ensure_free_space(to: &Path, len: u64, options: &DiffOptions<'_>) -> io::Result<()> {
+    if !options.check_free_space {
+        return Ok(());
+    }
+    count(Io::Metadata);
+    let to_len = match timed(Phase::Metadata, || fs::metadata(to)) {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => 0,
+    };
+    let needed = len.saturating_sub(to_len);
+    let dir = match to.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    count(Io::Metadata);
+    let available = timed(Phase::Metadata, || space::available(dir)).context(
+        Operation::Metadata,
+        Side::Destination,
+        dir,
+    )?;
+    match available {
+        Some(available) if available < needed => Err(WodError::InsufficientSpace {
+            path: to.to_path_buf(),
+            needed,
+            available,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+fn write_bytes(bytes: &[u8], to: &Path, options: &mut DiffOptions<'_>) -> io::Result<()> {
+    ensure_free_space(to, bytes.len() as u64, options)?;
+    unlink_dest_link(to, options)?;
+    timed(Phase::Copy, || {
+        let retry = options.retry;
+        let mode = options.create_mode;
+        let fs = options.file_system;
+        if let Some(on_progress) = &mut options.on_progress {
+            let total = bytes.len() as u64;
+            return retry
+                .run(|| {
+                    count(Io::Open);
+                    let mut file =
+                        open_dest(to, mode, fs).context(Operation::Write, Side::Destination, to)?;
+                    count(Io::Write);
+                    copy::copy_chunked(&mut Cursor::new(bytes), &mut file, None, to, |copied| {
+                        on_progress(to, copied, total)
+                    })?;
+                    file.flush()
+                        .context(Operation::Write, Side::Destination, to)
+                })
+                .map(|_| ());
+        }
+        retry
+            .run(|| {
+                count(Io::Open);
+                let mut file = open_dest(to, mode, fs)?;
+                count(Io::Write);
+                io::copy(&mut Cursor::new(bytes), &mut file)?;
+                file.flush()
+            })
+            .context(Operation::Write, Side::Destination, to)
+            .map(|_| ())
+    })
+}
+
+/// Open `to` for writing, truncated. A missing `to` is created with `mode`
+/// if given, on Unix, set exactly whatever the umask before any content is
+/// written.
+fn create_dest(to: &Path, mode: Option<u32>) -> io::Result<File> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        match File::options()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(to)
+        {
+            Ok(file) => {
+                // The umask can only have removed permission bits.
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    File::create(to)
+}
+
+/// Open `to` for writing on `fs`, or with [`create_dest`] on the local
+/// filesystem.
+fn open_dest<'f>(
+    to: &Path,
+    mode: Option<u32>,
+    fs: Option<&'f dyn FileSystem>,
+) -> io::Result<Box<dyn Write + 'f>> {
+    match fs {
+        Some(fs) => fs.create_write(to),
+        None => Ok(Box::new(create_dest(to, mode)?)),
+    }
+}
+
+/// Copy `from` to `to`, checking the source wasn't modified meanwhile as
+/// asked by [`DiffOptions::on_source_change`].
+fn copy_file(from: &Path, to: &Path, options: &mut DiffOptions<'_>) -> io::Result<u64> {
+    let stamp = |from: &Path| -> io::Result<(u64, SystemTime)> {
+        count(Io::Metadata);
+        let metadata = timed(Phase::Metadata, || fs::metadata(from)).context(
+            Operation::Metadata,
+            Side::Source,
+            from,
+        )?;
+        let mtime = metadata.modified();
+        Ok((
+            metadata.len(),
+            mtime.context(Operation::Metadata, Side::Source, from)?,
+        ))
+    };
+    let mut retries = 0;
+    loop {
+        if options.on_source_change == SourceChange::Ignore {
+            return copy_file_once(from, to, options);
+        }
+        let before = stamp(from)?;
+        let copied = copy_file_once(from, to, options)?;
+        if stamp(from)? == before {
+            return Ok(copied);
+        }
+        match options.on_source_change {
+            SourceChange::Retry { attempts } if retries < attempts => retries += 1,
+            _ => {
+                let path = from.to_path_buf();
+                return Err(WodError::SourceChangedDuringCopy { path }.into());
+            }
+        }
+    }
+}
+
+fn copy_file_once(from: &Path, to: &Path, options: &mut DiffOptions<'_>) -> io::Result<u64> {
+    if options.check_free_space {
+        count(Io::Metadata);
+        let len = timed(Phase::Metadata, || fs::metadata(from))
+            .context(Operation::Metadata, Side::Source, from)?
+            .len();
+        ensure_free_space(to, len, options)?;
+    }
+    unlink_dest_link(to, options)?;
+    timed(Phase::Copy, || {
+        let retry = options.retry;
+        let mode = options.create_mode;
+        if let Some(fs) = options.file_system {
+            return retry
+                .run(|| {
+                    count(Io::Copy);
+                    fs.copy(from, to)
+                })
+                .context(Operation::Copy, Side::Destination, to);
+        }
+        count(Io::Metadata);
+        if mode.is_some() && !timed(Phase::Metadata, || to.exists()) {
+            // fs::copy gives new files the permissions of the source.
+            return retry.run(|| {
+                count(Io::Copy);
+                let mut reader = File::open(from).context(Operation::Read, Side::Source, from)?;
+                let total = reader
+                    .metadata()
+                    .context(Operation::Metadata, Side::Source, from)?
+                    .len();
+                let mut writer =
+                    create_dest(to, mode).context(Operation::Write, Side::Destination, to)?;
+                copy::copy_chunked(&mut reader, &mut writer, Some(from), to, |copied| {
+                    if let Some(on_progress) = &mut options.on_progress {
+                        on_progress(to, copied, total);
+                    }
+                })
+            });
+        }
+        #[cfg(target_os = "linux")]
+        if options.sparse {
+            return retry.run(|| {
+                count(Io::Copy);
+                copy::copy_file_sparse(from, to)
+            });
+        }
+        if let Some(on_progress) = &mut options.on_progress {
+            return retry.run(|| {
+                count(Io::Copy);
+                copy::copy_file_with_progress(from, to, on_progress)
+            });
+        }
+        retry
+            .run(|| {
+                count(Io::Copy);
+                fs::copy(from, to)
+            })
+            .map_err(|error| {
+                // fs::copy doesn't say which side failed, check if the source is readable.
+                let (side, path, counterpart) = match File::open(from) {
+                    Ok(_) => (Side::Destination, to, from),
+                    Err(_) => (Side::Source, from, to),
+                };
+                DiffError {
+                    operation: Operation::Copy,
+                    side,
+                    path: path.to_path_buf(),
+                    counterpart: Some(counterpart.to_path_buf()),
+                    error,
+                }
+                .into()
+            })
+    })
+}
+
+/// Writes to a destination while feeding the same bytes into a hasher.
+struct Tee<W: Write, H: Hasher>(W, HashWriter<H>);
+
+impl<W: Write, H: Hasher> Write for Tee<W, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Write `from` to `to` hashing it along the way, returning the hash, so
+/// that [`DiffOptions::on_hash`] doesn't need a second read of the source.
+fn write_hashing<H: Hasher + Default>(
+    from: Source,
+    to: &Path,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<u64> {
+    if let Some(len) = from.len(options) {
+        ensure_free_space(to, len, options)?;
+    }
+    unlink_dest_link(to, options)?;
+    let mut digest = SourceDigest::new(options);
+    let mut reader = digest.reader(from.reader(options.fs())?);
+    count(Io::Open);
+    count(Io::Write);
+    let file = open_dest(to, options.create_mode, options.file_system).context(
+        Operation::Write,
+        Side::Destination,
+        to,
+    )?;
+    let mut tee = Tee(file, HashWriter::new(H::default()));
+    let total = from.len(options).unwrap_or(0);
+    timed(Phase::Copy, || {
+        copy::copy_chunked(&mut reader, &mut tee, from.path(), to, |copied| {
+            if let Some(on_progress) = &mut options.on_progress {
+                on_progress(to, copied, total);
+            }
+        })
+    })?;
+    tee.0
+        .flush()
+        .context(Operation::Write, Side::Destination, to)?;
+    if let (Some(from), None, true) = (from.path(), options.create_mode, options.local()) {
+        // Like fs::copy.
+        let permissions = fs::metadata(from)
+            .context(Operation::Metadata, Side::Source, from)?
+            .permissions();
+        fs::set_permissions(to, permissions).context(Operation::Write, Side::Destination, to)?;
+    }
+    digest.finish(options);
+    Ok(tee.1.finish())
+}
+
+fn same_content(mut a: impl BufRead, mut b: impl BufRead) -> io::Result<bool> {
+    loop {
+        let (x, y) = (a.fill_buf()?, b.fill_buf()?);
+        if x.is_empty() || y.is_empty() {
+            return Ok(x.is_empty() && y.is_empty());
+        }
+        let n = x.len().min(y.len());
+        if x[..n] != y[..n] {
+            return Ok(false);
+        }
+        a.consume(n);
+        b.consume(n);
+    }
+}
+
+/// Fail if reading the existing destination `to` failed and
+/// [`DiffOptions::dest_unreadable`] asks to, rather than overwriting it.
+fn ensure_dest_read<T>(
+    to: &Path,
+    to_read: &io::Result<T>,
+    options: &DiffOptions<'_>,
 ) -> io::Result<()> {
-    let to = to.as_ref();
-    let build_hasher = BuildHasherDefault::<H>::default();
-    let from_hash = {
-        let mut from_hash = HashWriter(build_hasher.build_hasher());
-        io::copy(&mut Cursor::new(from), &mut from_hash)?;
-        from_hash.0.finish()
+    match to_read {
+        Err(e)
+            if e.kind() != io::ErrorKind::NotFound
+                && options.dest_unreadable == DestUnreadable::Error =>
+        {
+            let e = DiffError::from_io(e).map_or(e, |e| &e.error);
+            let error = io::Error::new(e.kind(), e.to_string());
+            let path = to.to_path_buf();
+            Err(WodError::DestinationUnreadable { path, error }.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The outcome of writing to `to`, given the result of reading it beforehand.
+fn write_outcome<T>(to_read: &io::Result<T>) -> WriteOutcome {
+    match to_read {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => WriteOutcome::Created,
+        _ => WriteOutcome::Overwritten,
+    }
+}
+
+/// Carry the metadata of `from` asked to be preserved over to the freshly
+/// written `to`.
+fn finish_write(from: Option<&Path>, to: &Path, options: &DiffOptions<'_>) -> io::Result<()> {
+    let Some(from) = from else {
+        return Ok(());
     };
-    let to_hash = (|| -> Result<_, io::Error> {
-        let mut to_hash = HashWriter(build_hasher.build_hasher());
-        io::copy(&mut BufReader::new(File::open(to)?), &mut to_hash)?;
-        Ok(to_hash.0.finish())
-    })();
-    if to_hash.ok() != Some(from_hash) {
-        io::copy(&mut Cursor::new(from), &mut File::create(to)?)?;
+    if options.two_tier {
+        count(Io::Metadata);
+        let mtime = timed(Phase::Metadata, || fs::metadata(from)?.modified()).context(
+            Operation::Metadata,
+            Side::Source,
+            from,
+        )?;
+        count(Io::Write);
+        File::options()
+            .write(true)
+            .open(to)
+            .and_then(|file| file.set_modified(mtime))
+            .context(Operation::Write, Side::Destination, to)?;
+    }
+    if options.preserve_file_flags {
+        flags::copy_flags(from, to).context(Operation::Write, Side::Destination, to)?;
     }
     Ok(())
 }
 
-pub fn write_on_dir_diff<H: Hasher + Default>(
+/// Whether `from` and `to` are the same file, e.g. hardlinks of each other
+/// or the same path, so there's nothing to write.
+///
+/// Only detected on Unix, through the device and inode numbers. Reflinked
+/// copies have their own inode and still get hashed.
+fn same_file(from: &Path, to: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        count(Io::Metadata);
+        count(Io::Metadata);
+        match timed(Phase::Metadata, || (fs::metadata(from), fs::metadata(to))) {
+            (Ok(from), Ok(to)) => from.dev() == to.dev() && from.ino() == to.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (from, to);
+        false
+    }
+}
+
+/// Whether `to` must be left alone because it exists in
+/// [`WriteMode::CreateIfAbsent`].
+fn keep_existing(to: &Path, options: &DiffOptions<'_>) -> io::Result<bool> {
+    if options.write_mode != WriteMode::CreateIfAbsent {
+        return Ok(false);
+    }
+    count(Io::Metadata);
+    match timed(Phase::Metadata, || fs::symlink_metadata(to)) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).context(Operation::Metadata, Side::Destination, to),
+    }
+}
+
+/// Checks run once a write is needed, returns why it shouldn't happen after all.
+fn guard_write(
+    from: Option<&Path>,
+    to: &Path,
+    from_hash: u64,
+    to_hash: Option<u64>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<Option<SkipReason>> {
+    if let Some(cutoff) = options.skip_dest_modified_after {
+        count(Io::Metadata);
+        match timed(Phase::Metadata, || fs::metadata(to)?.modified()) {
+            Ok(mtime) if mtime > cutoff => return Ok(Some(SkipReason::DestNewer)),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(e).context(Operation::Metadata, Side::Destination, to)
+            }
+            _ => {}
+        }
+    }
+    if options.treat_empty_dest_as_present {
+        count(Io::Metadata);
+        if let Ok(metadata) = timed(Phase::Metadata, || fs::metadata(to)) {
+            if metadata.is_file() && metadata.len() == 0 {
+                return Ok(Some(SkipReason::EmptyDest));
+            }
+        }
+    }
+    conflict::resolve(from, to, from_hash, to_hash, options)
+}
+
+/// Called once hashes matched, returns whether the content is really the same.
+fn confirm_match(
+    to: &Path,
+    options: &mut DiffOptions<'_>,
+    same_content: impl FnOnce() -> io::Result<bool>,
+) -> io::Result<bool> {
+    if options.verify_on_match == VerifyOnMatch::Off || same_content()? {
+        return Ok(true);
+    }
+    options.hash_collisions += 1;
+    if options.verify_on_match == VerifyOnMatch::Error {
+        let path = to.to_path_buf();
+        return Err(WodError::HashCollision { path }.into());
+    }
+    if let Some(on_hash_collision) = &mut options.on_hash_collision {
+        on_hash_collision(to);
+    }
+    Ok(false)
+}
+
+fn write_on_source_diff<H: Hasher + Default>(
+    from: Source,
+    to: &Path,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<WriteOutcome> {
+    write_on_source_diff_hashing::<H>(from, to, options).map(|(outcome, _)| outcome)
+}
+
+/// [`write_on_source_diff`] also returning the hash of `from` when the diff
+/// computed it.
+fn write_on_source_diff_hashing<H: Hasher + Default>(
+    from: Source,
+    to: &Path,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<(WriteOutcome, Option<u64>)> {
+    let (outcome, from_hash) = diff_source::<H>(from, to, options).counterpart(from.path(), to)?;
+    touch_if_unchanged(to, outcome, options)?;
+    Ok((outcome, from_hash))
+}
+
+/// Bump the modification time of `to` when it was skipped for holding the
+/// source content already, as asked by [`DiffOptions::touch_on_skip`].
+fn touch_if_unchanged(
+    to: &Path,
+    outcome: WriteOutcome,
+    options: &DiffOptions<'_>,
+) -> io::Result<()> {
+    let WriteOutcome::Skipped(reason) = outcome else {
+        return Ok(());
+    };
+    let unchanged = matches!(
+        reason,
+        SkipReason::HashMatch
+            | SkipReason::SizeAndHashMatch
+            | SkipReason::BothEmpty
+            | SkipReason::Equivalent
+    );
+    if !options.touch_on_skip || !unchanged || options.assert_unchanged {
+        return Ok(());
+    }
+    count(Io::Write);
+    File::options()
+        .write(true)
+        .open(to)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+        .context(Operation::Write, Side::Destination, to)
+}
+
+fn diff_source<H: Hasher + Default>(
+    from: Source,
+    to: &Path,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<(WriteOutcome, Option<u64>)> {
+    if keep_existing(to, options)? {
+        return Ok((WriteOutcome::Skipped(SkipReason::DestExists), None));
+    }
+    if let Some(from) = from.path().filter(|_| options.local()) {
+        if same_file(from, to) {
+            return Ok((WriteOutcome::Skipped(SkipReason::SameInode), None));
+        }
+    }
+    let codec = options.codec(to);
+    let text = options.text_for(to).cloned();
+    if text.is_some()
+        || codec.is_some()
+        || options.compare.is_some()
+        || options.comparators.is_some()
+        || options.crypt.is_some()
+        || options.canonicalize.is_some()
+    {
+        let text = text.unwrap_or_default();
+        return write_on_content_diff::<H>(from, to, &text, codec, options)
+            .map(|outcome| (outcome, None));
+    }
+    let fs = options.fs();
+    let from_len = from.len(options);
+    count(Io::Metadata);
+    let to_len = timed(Phase::Metadata, || fs.metadata(to)).map(|metadata| metadata.len);
+    if from_len == Some(0) && matches!(to_len, Ok(0)) {
+        return Ok((WriteOutcome::Skipped(SkipReason::BothEmpty), None));
+    }
+    let same_len = matches!((from_len, &to_len), (Some(a), Ok(b)) if a == *b);
+    // Sizes tell the content apart without hashing, unless the hashes are
+    // needed to check for conflicts or to fill sidecars.
+    let hashes_needed =
+        (options.baseline.is_some() && options.on_conflict.is_some()) || options.sidecar.is_some();
+    let sampled = match options.sample {
+        Some(sample) if same_len && !hashes_needed => {
+            Some(ends_differ(from, to, sample.head, sample.tail)?)
+        }
+        _ => None,
+    };
+    if sampled == Some(false) {
+        return Ok((WriteOutcome::Skipped(SkipReason::Sampled), None));
+    }
+    let (from_hash, to_hash, outcome) = if from_len.is_some()
+        && to_len.is_ok()
+        && !hashes_needed
+        && (!same_len
+            || sampled == Some(true)
+            || (options.two_tier && fingerprints_differ(from, to)?))
+    {
+        (None, None, write_outcome(&to_len))
+    } else {
+        let from_hash = from.hash::<H>(options)?;
+        let to_hash = dest_hash::<H>(to, options);
+        ensure_dest_read(to, &to_hash, options)?;
+        let unchanged = matches!(to_hash, Ok(to_hash) if to_hash == from_hash)
+            && confirm_match(to, options, || {
+                count(Io::Open);
+                count(Io::Read);
+                let to_file = fs
+                    .open_read(to)
+                    .context(Operation::Read, Side::Destination, to)?;
+                same_content(from.reader(fs)?, BufReader::new(to_file))
+            })?;
+        if unchanged {
+            let reason = if same_len {
+                SkipReason::SizeAndHashMatch
+            } else {
+                SkipReason::HashMatch
+            };
+            return Ok((WriteOutcome::Skipped(reason), Some(from_hash)));
+        }
+        let outcome = write_outcome(&to_hash);
+        (Some(from_hash), to_hash.ok(), outcome)
+    };
+    // Without a hash, the conflict check doesn't apply.
+    let guard_hash = from_hash.unwrap_or(0);
+    if let Some(reason) = guard_write(from.path(), to, guard_hash, to_hash, options)? {
+        return Ok((WriteOutcome::Skipped(reason), from_hash));
+    }
+    ensure_writable(to, options)?;
+    if outcome == WriteOutcome::Overwritten {
+        if let Some(on_similarity) = &mut options.on_similarity {
+            let mut from_content = Vec::new();
+            count(Io::Read);
+            from.reader(fs)?.read_to_end(&mut from_content).context(
+                Operation::Read,
+                Side::Source,
+                from.path().unwrap_or(to),
+            )?;
+            count(Io::Read);
+            let to_content = vfs::read(fs, to).context(Operation::Read, Side::Destination, to)?;
+            on_similarity(to, similarity::similarity(&from_content, &to_content));
+        }
+        backup_dest(to, options)?;
+    }
+    let from_hash = match from_hash {
+        None if options.on_hash.is_some() || digest::enabled(options) => {
+            Some(write_hashing::<H>(from, to, options)?)
+        }
+        from_hash => {
+            from.write_to(to, options)?;
+            from_hash
+        }
+    };
+    finish_write(from.path(), to, options)?;
+    if let Some(on_hash) = &mut options.on_hash {
+        on_hash(to, from_hash.unwrap_or(0));
+    }
+    if let Some(sidecar) = &mut options.sidecar {
+        let sidecar = sidecar(to);
+        sidecar::write(&sidecar, to, from_hash.unwrap_or(0)).context(
+            Operation::Write,
+            Side::Destination,
+            &sidecar,
+        )?;
+    }
+    Ok((outcome, from_hash))
+}
+
+/// Size of the ends of files compared by [`DiffOptions::two_tier`].
+const FINGERPRINT_LEN: u64 = 4 * 1024;
+
+/// Whether the cheap fingerprints of [`DiffOptions::two_tier`] tell `from`
+/// and `to` apart, given they have the same size.
+fn fingerprints_differ(from: Source, to: &Path) -> io::Result<bool> {
+    if let Source::File(from) = from {
+        count(Io::Metadata);
+        count(Io::Metadata);
+        let mtimes = timed(Phase::Metadata, || {
+            Ok::<_, io::Error>((
+                fs::metadata(from)?.modified()?,
+                fs::metadata(to)?.modified()?,
+            ))
+        });
+        if !matches!(mtimes, Ok((a, b)) if a == b) {
+            return Ok(true);
+        }
+    }
+    ends_differ(from, to, FINGERPRINT_LEN, FINGERPRINT_LEN)
+}
+
+/// Whether the first `head` or the last `tail` bytes of `from` and `to`
+/// differ.
+fn ends_differ(from: Source, to: &Path, head: u64, tail: u64) -> io::Result<bool> {
+    let ends = |path: &Path| -> io::Result<(Vec<u8>, Vec<u8>)> {
+        count(Io::Open);
+        count(Io::Read);
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut head_bytes = Vec::new();
+        (&mut file).take(head).read_to_end(&mut head_bytes)?;
+        let mut tail_bytes = Vec::new();
+        file.seek(SeekFrom::Start(len.saturating_sub(tail)))?;
+        file.take(tail).read_to_end(&mut tail_bytes)?;
+        Ok((head_bytes, tail_bytes))
+    };
+    let from_ends = match from {
+        Source::File(path) => ends(path).context(Operation::Read, Side::Source, path)?,
+        Source::Bytes(bytes) => {
+            let head = bytes.len().min(head as usize);
+            let tail = bytes.len().min(tail as usize);
+            (bytes[..head].to_vec(), bytes[bytes.len() - tail..].to_vec())
+        }
+    };
+    let to_ends = ends(to).context(Operation::Read, Side::Destination, to)?;
+    Ok(from_ends != to_ends)
+}
+
+/// The hash of the destination `to`, taken from its sidecar while it's
+/// valid, see [`DiffOptions::sidecar`].
+fn dest_hash<H: Hasher + Default>(to: &Path, options: &mut DiffOptions<'_>) -> io::Result<u64> {
+    let Some(sidecar) = &mut options.sidecar else {
+        return hash_file::<H>(to, options);
+    };
+    let sidecar = sidecar(to);
+    count(Io::Read);
+    if let Some(hash) = sidecar::read(&sidecar, to) {
+        return Ok(hash);
+    }
+    let hash = hash_file::<H>(to, options)?;
+    if !options.assert_unchanged {
+        // Only saves hashing next time, a failure is no reason to stop.
+        count(Io::Write);
+        let _ = sidecar::write(&sidecar, to, hash);
+    }
+    Ok(hash)
+}
+
+/// Diff holding both sides in memory, to compare them normalized by `text`,
+/// with the destination decrypted and decompressed by `codec`, or with a
+/// custom comparator.
+fn write_on_content_diff<H: Hasher + Default>(
+    from: Source,
+    to: &Path,
+    text: &TextOptions,
+    codec: Option<Codec>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<WriteOutcome> {
+    let from_path = from.path();
+    let from = match from {
+        Source::File(path) => options
+            .retry
+            .run(|| {
+                count(Io::Open);
+                count(Io::Read);
+                timed(Phase::Hash, || vfs::read(options.fs(), path))
+            })
+            .context(Operation::Read, Side::Source, path)?,
+        Source::Bytes(bytes) => bytes.to_vec(),
+    };
+    let from_text = text.normalize(&from);
+    let from_normalized = canonicalized(&from_text, &mut options.canonicalize);
+    let from_hash = hash_reader::<H>(&*from_normalized)?;
+    let comparator = match (&options.compare, &options.comparators) {
+        (None, Some(comparators)) => comparators.sniff(&from_normalized),
+        _ => None,
+    };
+    let to_content = options.retry.run(|| {
+        count(Io::Open);
+        count(Io::Read);
+        timed(Phase::Hash, || vfs::read(options.fs(), to))
+    });
+    ensure_dest_read(to, &to_content, options)?;
+    let mut to_hash = None;
+    let to_decrypted = match (&to_content, &mut options.crypt) {
+        (Ok(to_content), Some((decrypt, _))) => decrypt(to, to_content).ok().map(Cow::Owned),
+        (Ok(to_content), None) => Some(Cow::Borrowed(&to_content[..])),
+        (Err(_), _) => None,
+    };
+    let to_decoded = match (to_decrypted, codec) {
+        (Some(to_content), Some(codec)) => codec.decode(&to_content).ok().map(Cow::Owned),
+        (to_content, _) => to_content,
+    };
+    if let Some(to_content) = &to_decoded {
+        let to_text = text.normalize(to_content);
+        let to_normalized = canonicalized(&to_text, &mut options.canonicalize);
+        to_hash = Some(hash_reader::<H>(&*to_normalized)?);
+        if let Some(compare) = &mut options.compare {
+            if compare(&from_normalized, &to_normalized) {
+                return Ok(WriteOutcome::Skipped(SkipReason::Equivalent));
+            }
+        } else if let Some(comparator) = &comparator {
+            if comparator.equivalent(&from_normalized, &to_normalized) {
+                return Ok(WriteOutcome::Skipped(SkipReason::Equivalent));
+            }
+        } else if to_hash == Some(from_hash)
+            && confirm_match(to, options, || Ok(from_normalized == to_normalized))?
+        {
+            return Ok(WriteOutcome::Skipped(SkipReason::HashMatch));
+        }
+    }
+    if let Some(reason) = guard_write(from_path, to, from_hash, to_hash, options)? {
+        return Ok(WriteOutcome::Skipped(reason));
+    }
+    let output = text.output(&from);
+    let output = match codec {
+        Some(codec) => Cow::Owned(codec.encode(&output)?),
+        None => output,
+    };
+    let output = match &mut options.crypt {
+        Some((_, encrypt)) => {
+            Cow::Owned(encrypt(to, &output).context(Operation::Write, Side::Destination, to)?)
+        }
+        None => output,
+    };
+    ensure_writable(to, options)?;
+    if let Ok(to_content) = &to_content {
+        if let Some(on_similarity) = &mut options.on_similarity {
+            on_similarity(to, similarity::similarity(&from, to_content));
+        }
+        backup_dest(to, options)?;
+    }
+    write_bytes(&output, to, options)?;
+    finish_write(from_path, to, options)?;
+    Ok(write_outcome(&to_content))
+}
+
+/// `bytes` as rewritten by [`DiffOptions::canonicalize`], if set.
+fn canonicalized<'b>(
+    bytes: &'b [u8],
+    canonicalize: &mut Option<Canonicalize<'_>>,
+) -> Cow<'b, [u8]> {
+    match canonicalize {
+        Some(canonicalize) => canonicalize(bytes),
+        None => Cow::Borrowed(bytes),
+    }
+}
+
+/// Whether `to` needs to be written to hold content hashing to `from_hash`,
+/// which is true if it doesn't exist.
+///
+/// `from_hash` must come from the same hasher, e.g. through [`HashWriter`].
+pub fn needs_write<H: Hasher + Default>(from_hash: u64, to: impl AsRef<Path>) -> io::Result<bool> {
+    let to = to.as_ref();
+    match File::open(to) {
+        Ok(file) => Ok(hash_reader::<H>(BufReader::new(file))? != from_hash),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e).context(Operation::Read, Side::Destination, to),
+    }
+}
+
+pub fn write_on_file_diff<H: Hasher + Default>(
     from: impl AsRef<Path>,
     to: impl AsRef<Path>,
 ) -> io::Result<()> {
+    write_on_file_diff_with_options::<H>(from, to, &mut DiffOptions::default()).map(|_| ())
+}
+
+pub fn write_on_file_diff_with_options<H: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<WriteOutcome> {
     let from = from.as_ref();
+    // Missing sources are left to fail when read.
+    count(Io::Metadata);
+    let not_a_file = if options.local() {
+        let metadata = timed(Phase::Metadata, || fs::metadata(from));
+        metadata
+            .ok()
+            .filter(|metadata| !metadata.is_file())
+            .map(|metadata| metadata.is_dir())
+    } else {
+        let metadata = timed(Phase::Metadata, || options.fs().metadata(from));
+        metadata
+            .ok()
+            .filter(|metadata| metadata.is_dir)
+            .map(|_| true)
+    };
+    if let Some(is_dir) = not_a_file {
+        let path = from.to_path_buf();
+        return Err(WodError::NotAFile { path, is_dir }.into());
+    }
+    write_on_source_diff::<H>(Source::File(from), to.as_ref(), options)
+}
+
+/// [`write_on_file_diff`] returning the number of bytes written, 0 when
+/// skipped, like [`fs::copy`] does.
+pub fn write_on_file_diff_len<H: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> io::Result<u64> {
     let to = to.as_ref();
-    if !to.exists() {
-        fs::create_dir_all(to)?;
+    let outcome = write_on_file_diff_with_options::<H>(from, to, &mut DiffOptions::default())?;
+    match outcome {
+        WriteOutcome::Skipped(_) => Ok(0),
+        _ => Ok(fs::metadata(to)
+            .context(Operation::Metadata, Side::Destination, to)?
+            .len()),
     }
+}
 
-    for entry in fs::read_dir(from)? {
-        let entry = entry?;
-        let from_path = entry.path();
-        let to_path = to.join(entry.file_name());
+pub fn write_on_bytes_diff<H: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+) -> io::Result<()> {
+    write_on_bytes_diff_with_options::<H>(from, to, &mut DiffOptions::default()).map(|_| ())
+}
 
-        if from_path.is_dir() {
-            write_on_dir_diff::<H>(&from_path, &to_path)?;
-        } else {
-            if to_path.exists() {
-                write_on_file_diff::<H>(&from_path, &to_path)?;
-            } else {
-                fs::copy(&from_path, &to_path)?;
-            }
-        }
-    }
-    Ok(())
+pub fn write_on_bytes_diff_with_options<H: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<WriteOutcome> {
+    write_on_source_diff::<H>(Source::Bytes(from), to.as_ref(), options)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rustc_hash::FxHasher;
-    use std::fs;
+/// [`write_on_bytes_diff`] returning the number of bytes written, 0 when
+/// skipped, like [`fs::copy`] does.
+pub fn write_on_bytes_diff_len<H: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+) -> io::Result<u64> {
+    let outcome = write_on_bytes_diff_with_options::<H>(from, to, &mut DiffOptions::default())?;
+    Ok(match outcome {
+        WriteOutcome::Skipped(_) => 0,
+        _ => from.len() as u64,
+    })
+}
+
+/// [`write_on_bytes_diff`] to each of `tos`, hashing the source only once.
+///
+/// Returns the outcome of every destination in order, or stops at the first
+/// failing one.
+pub fn write_on_bytes_diff_multi<H: Hasher + Default>(
+    from: &[u8],
+    tos: &[impl AsRef<Path>],
+) -> io::Result<Vec<WriteOutcome>> {
+    let options = &mut DiffOptions::default();
+    let from_hash = hash_reader::<H>(from)?;
+    tos.iter()
+        .map(|to| {
+            let to = to.as_ref();
+            let to_hash = hash_file::<H>(to, options);
+            if matches!(to_hash, Ok(to_hash) if to_hash == from_hash) {
+                return Ok(WriteOutcome::Skipped(SkipReason::HashMatch));
+            }
+            write_bytes(from, to, options)?;
+            Ok(write_outcome(&to_hash))
+        })
+        .collect()
+}
+
+/// [`write_on_bytes_diff`] also reporting the final hash and size of `to`,
+/// known from the source without reading the destination again.
+pub fn upsert_bytes<H: Hasher + Default>(
+    from: &[u8],
+    to: impl AsRef<Path>,
+) -> io::Result<UpsertReport> {
+    // Hashing while writing, so that the hash comes from the diff whatever
+    // it ends up doing.
+    let options = &mut DiffOptions::default().on_hash(|_, _| {});
+    let (outcome, hash) =
+        write_on_source_diff_hashing::<H>(Source::Bytes(from), to.as_ref(), options)?;
+    let hash = match hash {
+        Some(hash) => hash,
+        // Skipped before hashing, e.g. both empty.
+        None => hash_reader::<H>(from)?,
+    };
+    Ok(UpsertReport {
+        outcome,
+        hash,
+        len: from.len() as u64,
+    })
+}
+
+/// Write the content returned by `fetch` to `to` unless the destination
+/// already hashes to `fingerprint`.
+///
+/// For sources that are expensive to get, e.g. downloaded, but whose hash is
+/// known upfront: `fetch` is only called when a write is needed. Same as
+/// [`needs_write`], `fingerprint` must come from the same hasher.
+pub fn write_on_lazy_bytes_diff<H: Hasher + Default>(
+    to: impl AsRef<Path>,
+    fingerprint: u64,
+    fetch: impl FnOnce() -> io::Result<Vec<u8>>,
+) -> io::Result<()> {
+    write_on_lazy_bytes_diff_with_options::<H>(to, fingerprint, fetch, &mut DiffOptions::default())
+        .map(|_| ())
+}
+
+pub fn write_on_lazy_bytes_diff_with_options<H: Hasher + Default>(
+    to: impl AsRef<Path>,
+    fingerprint: u64,
+    fetch: impl FnOnce() -> io::Result<Vec<u8>>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<WriteOutcome> {
+    let to = to.as_ref();
+    if keep_existing(to, options)? {
+        return Ok(WriteOutcome::Skipped(SkipReason::DestExists));
+    }
+    let to_hash = hash_file::<H>(to, options).context(Operation::Read, Side::Destination, to);
+    ensure_dest_read(to, &to_hash, options)?;
+    if matches!(to_hash, Ok(to_hash) if to_hash == fingerprint) {
+        let outcome = WriteOutcome::Skipped(SkipReason::HashMatch);
+        touch_if_unchanged(to, outcome, options)?;
+        return Ok(outcome);
+    }
+    let outcome = write_outcome(&to_hash);
+    let to_hash = to_hash.ok();
+    if let Some(reason) = guard_write(None, to, fingerprint, to_hash, options)? {
+        return Ok(WriteOutcome::Skipped(reason));
+    }
+    ensure_writable(to, options)?;
+    let from = fetch()?;
+    if outcome == WriteOutcome::Overwritten {
+        backup_dest(to, options)?;
+    }
+    write_bytes(&from, to, options)?;
+    Ok(outcome)
+}
+
+/// Write everything read from `from` to `to` if the content is different.
+///
+/// The reader is buffered in memory once, so non-seekable sources such as
+/// stdin work. A command line tool accepting `-` as "read stdin" can do:
+///
+/// ```no_run
+/// # use rustc_hash::FxHasher;
+/// # use std::{fs::File, io};
+/// # fn main() -> io::Result<()> {
+/// let source = std::env::args().nth(1).unwrap();
+/// if source == "-" {
+///     wod::write_on_reader_diff::<FxHasher>(io::stdin().lock(), "out.txt")?;
+/// } else {
+///     wod::write_on_reader_diff::<FxHasher>(File::open(source)?, "out.txt")?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_on_reader_diff<H: Hasher + Default>(
+    from: impl Read,
+    to: impl AsRef<Path>,
+) -> io::Result<()> {
+    write_on_reader_diff_with_options::<H>(from, to, &mut DiffOptions::default()).map(|_| ())
+}
+
+pub fn write_on_reader_diff_with_options<H: Hasher + Default>(
+    mut from: impl Read,
+    to: impl AsRef<Path>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<WriteOutcome> {
+    let mut buf = Vec::new();
+    from.read_to_end(&mut buf)?;
+    write_on_bytes_diff_with_options::<H>(&buf, to, options)
+}
+
+pub fn write_on_dir_diff<H: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> io::Result<()> {
+    write_on_dir_diff_with_options::<H>(from, to, &mut DiffOptions::default()).map(|_| ())
+}
+
+pub fn write_on_dir_diff_with_options<H: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<DirDiffStats> {
+    let from = &match &options.strip_prefix {
+        Some(prefix) => from.as_ref().join(prefix),
+        None => from.as_ref().to_path_buf(),
+    };
+    diff_into(to.as_ref(), options, |walk, to| {
+        if walk.options.local() {
+            walk.skip = nested_dest(from, to)?;
+        }
+        walk.run::<H>(from, to)
+    })
+}
+
+/// `path` made absolute with symlinks resolved, as far as it exists.
+fn canonical(path: &Path) -> io::Result<PathBuf> {
+    let mut rest = Vec::new();
+    for ancestor in path.ancestors() {
+        if ancestor.as_os_str().is_empty() {
+            break;
+        }
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return Ok(rest
+                .into_iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name)));
+        }
+        rest.extend(ancestor.file_name());
+    }
+    Ok(std::env::current_dir()?.join(path))
+}
+
+/// The source directory to leave out of a diff of `from` into `to` because
+/// it's the destination itself. Fails with [`WodError::NestedDirs`] when the
+/// destination holds the source instead, in which case writes and mirror
+/// deletions would hit the source.
+fn nested_dest(from: &Path, to: &Path) -> io::Result<Option<PathBuf>> {
+    let from_canonical = canonical(from).context(Operation::Metadata, Side::Source, from)?;
+    let to_canonical = canonical(to).context(Operation::Metadata, Side::Destination, to)?;
+    if from_canonical.starts_with(&to_canonical) {
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        return Err(WodError::NestedDirs { from, to }.into());
+    }
+    Ok(to_canonical
+        .strip_prefix(&from_canonical)
+        .ok()
+        .map(|rel| from.join(rel)))
+}
+
+pub fn write_on_paths_diff<H: Hasher + Default>(
+    from_root: impl AsRef<Path>,
+    to_root: impl AsRef<Path>,
+    paths: &[PathBuf],
+) -> io::Result<()> {
+    write_on_paths_diff_with_options::<H>(from_root, to_root, paths, &mut DiffOptions::default())
+        .map(|_| ())
+}
+
+/// [`write_on_dir_diff_with_options`] restricted to the files at `paths`,
+/// relative to both roots, e.g. as reported by a file watcher, so the trees
+/// aren't walked. Missing parent directories are created.
+///
+/// A path missing from the source has its destination deleted when
+/// mirroring, a path naming a source directory fails with
+/// [`WodError::NotAFile`]. Ignore files aren't looked at.
+pub fn write_on_paths_diff_with_options<H: Hasher + Default>(
+    from_root: impl AsRef<Path>,
+    to_root: impl AsRef<Path>,
+    paths: &[PathBuf],
+    options: &mut DiffOptions<'_>,
+) -> io::Result<DirDiffStats> {
+    let from_root = from_root.as_ref();
+    let strip_prefix = options.strip_prefix.clone();
+    diff_into(to_root.as_ref(), options, |walk, to_root| {
+        for path in paths {
+            if walk.stopped() {
+                break;
+            }
+            let from = from_root.join(path);
+            let rel = match &strip_prefix {
+                Some(prefix) => match path.strip_prefix(prefix) {
+                    Ok(rel) => rel,
+                    Err(_) => continue,
+                },
+                None => path,
+            };
+            if let Err(e) = walk.changed::<H>(&from, to_root.join(rel), rel) {
+                walk.fail(e, &from)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Run a directory diff into `to` through `run`, handling the setup and the
+/// stats shared by all of them.
+fn diff_into<'a>(
+    to: &Path,
+    options: &mut DiffOptions<'a>,
+    run: impl FnOnce(&mut DirWalk<'_, '_, 'a>, &Path) -> io::Result<()>,
+) -> io::Result<DirDiffStats> {
+    let to = &match &options.dest_prefix {
+        Some(prefix) => to.join(prefix),
+        None => to.to_path_buf(),
+    };
+    #[cfg(feature = "instrument")]
+    let io_before = IoCounters::current();
+    #[cfg(feature = "timing")]
+    let timings_before = Timings::current();
+    let journal = match &options.journal {
+        Some((path, session)) => Some(journal::Journal::open(path, session).context(
+            Operation::Read,
+            Side::Destination,
+            path,
+        )?),
+        None => None,
+    };
+    let mut walk = DirWalk {
+        to_root: to,
+        options,
+        stats: DirDiffStats::default(),
+        journal,
+        links: HashMap::new(),
+        changed: Vec::new(),
+        skip: None,
+        bytes_written: 0,
+        #[cfg(feature = "ignore")]
+        ignores: ignore_files::IgnoreStack::default(),
+    };
+    let dest_root = walk.options.dest_root.replace(to.clone());
+    let hash_collisions = walk.options.hash_collisions;
+    let walked = run(&mut walk, to);
+    walk.options.dest_root = dest_root;
+    walk.stats.hash_collisions = walk.options.hash_collisions - hash_collisions;
+    walked?;
+    #[cfg(feature = "instrument")]
+    {
+        walk.stats.io = IoCounters::current().since(io_before);
+    }
+    #[cfg(feature = "timing")]
+    {
+        walk.stats.timings = Timings::current().since(timings_before);
+    }
+    if let Some(journal) = walk.journal {
+        if walk.stats.errors.is_empty() && !walk.stats.cancelled && !walk.stats.quota_reached {
+            journal.finish()?;
+        }
+    }
+    if !walk.changed.is_empty() {
+        let paths = walk.changed;
+        return Err(WodError::Changed { paths }.into());
+    }
+    if let Some(on_finish) = &mut walk.options.on_finish {
+        on_finish(&walk.stats);
+    }
+    Ok(walk.stats)
+}
+
+/// Fail with [`io::ErrorKind::InvalidData`] if the source path `rel` is
+/// absolute or has `..` components, which would escape the destination.
+fn ensure_relative(rel: &Path) -> io::Result<()> {
+    if rel
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Ok(());
+    }
+    let message = format!("unsafe source path {}", rel.display());
+    Err(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// State of a directory diff while walking the source tree.
+struct DirWalk<'r, 'o, 'a> {
+    to_root: &'r Path,
+    options: &'o mut DiffOptions<'a>,
+    stats: DirDiffStats,
+    journal: Option<journal::Journal>,
+    /// The first destination holding each source hash, for
+    /// [`DiffOptions::dedup`].
+    links: HashMap<u64, PathBuf>,
+    /// Destinations that would have changed, under
+    /// [`DiffOptions::assert_unchanged`].
+    changed: Vec<PathBuf>,
+    /// The source directory that is the destination, not to be walked.
+    skip: Option<PathBuf>,
+    /// Size of the destinations written so far, for
+    /// [`DiffOptions::max_bytes_written`].
+    bytes_written: u64,
+    #[cfg(feature = "ignore")]
+    ignores: ignore_files::IgnoreStack,
+}
+
+/// A directory being walked.
+struct Frame {
+    from: PathBuf,
+    to: PathBuf,
+    /// The path of `from` relative to the source root.
+    rel: PathBuf,
+    /// Source names left to diff with their destination name, in reverse
+    /// order.
+    names: Vec<(OsString, Option<OsString>)>,
+    /// Destination names of the source entries, kept by mirroring.
+    kept: HashSet<OsString>,
+    #[cfg(feature = "ignore")]
+    pushed: bool,
+}
+
+impl DirWalk<'_, '_, '_> {
+    /// Diff `from` into `to`, walking subdirectories through a stack of
+    /// frames on the heap instead of recursing, so the depth of the tree
+    /// isn't bounded by the call stack.
+    fn run<H: Hasher + Default>(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let root = self.open(from.to_path_buf(), to.to_path_buf(), PathBuf::new())?;
+        let mut stack = vec![root];
+        while let Some(frame) = stack.last_mut() {
+            if self.stopped() {
+                return Ok(());
+            }
+            let Some((name, to_name)) = frame.names.pop() else {
+                let frame = stack.pop().unwrap();
+                self.close(frame)?;
+                continue;
+            };
+            let from_path = frame.from.join(&name);
+            let rel_path = frame.rel.join(&name);
+            let Some(to_name) = to_name else {
+                let reason = SkipReason::CaseCollision;
+                let to_path = frame.to.join(&name);
+                self.record(&to_path, WriteOutcome::Skipped(reason));
+                continue;
+            };
+            let to_path = frame.to.join(&to_name);
+            if self.mirror() {
+                frame.kept.insert(to_name);
+            }
+            match self.entry::<H>(&from_path, to_path, rel_path) {
+                Ok(Some(child)) => stack.push(child),
+                Ok(None) => {}
+                Err(e) => self.fail(e, &from_path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether to stop as asked by [`DiffOptions::cancel`] or because
+    /// [`DiffOptions::max_bytes_written`] is reached, which is then recorded
+    /// in the stats.
+    fn stopped(&mut self) -> bool {
+        let cancelled = self
+            .options
+            .cancel
+            .is_some_and(|cancel| cancel.load(atomic::Ordering::Relaxed));
+        self.stats.cancelled |= cancelled;
+        let quota_reached = self
+            .options
+            .max_bytes_written
+            .is_some_and(|max| self.bytes_written >= max);
+        self.stats.quota_reached |= quota_reached;
+        cancelled || quota_reached
+    }
+
+    fn mirror(&self) -> bool {
+        self.options.mirror && self.options.map_path.is_none()
+    }
+
+    /// Start walking the directory `from`, creating its destination `to`.
+    fn open(&mut self, from: PathBuf, to: PathBuf, rel: PathBuf) -> io::Result<Frame> {
+        let fs = self.options.fs();
+        count(Io::Metadata);
+        if self.options.map_path.is_none() && timed(Phase::Metadata, || fs.metadata(&to)).is_err() {
+            if self.options.assert_unchanged {
+                let path = self.reported(&to);
+                self.changed.push(path);
+            } else {
+                self.create_dir(&to)?;
+            }
+        }
+        let options = &mut *self.options;
+
+        let names = options
+            .retry
+            .run(|| {
+                count(Io::ReadDir);
+                let paths = fs.read_dir(&from)?;
+                Ok(paths
+                    .into_iter()
+                    .filter_map(|path| path.file_name().map(OsString::from))
+                    .collect())
+            })
+            .context(Operation::ReadDir, Side::Source, &from)?;
+        let mut names = case::resolve(&from, &to, names, options.case_collisions).context(
+            Operation::ReadDir,
+            Side::Destination,
+            &to,
+        )?;
+        match options.entry_order {
+            EntryOrder::Native => {}
+            EntryOrder::Name => names.sort(),
+            EntryOrder::NewestFirst => {
+                names.sort_by_cached_key(|(name, _)| {
+                    count(Io::Metadata);
+                    let mtime = timed(Phase::Metadata, || {
+                        fs::metadata(from.join(name))?.modified()
+                    });
+                    (
+                        Reverse(mtime.unwrap_or(SystemTime::UNIX_EPOCH)),
+                        name.clone(),
+                    )
+                });
+            }
+        }
+        names.reverse();
+        #[cfg(feature = "ignore")]
+        let pushed = options.ignore_files
+            && self
+                .ignores
+                .push(&from)
+                .context(Operation::Read, Side::Source, &from)?;
+        Ok(Frame {
+            from,
+            to,
+            rel,
+            names,
+            kept: HashSet::new(),
+            #[cfg(feature = "ignore")]
+            pushed,
+        })
+    }
+
+    /// Finish walking a directory once all its entries are done.
+    fn close(&mut self, frame: Frame) -> io::Result<()> {
+        #[cfg(feature = "ignore")]
+        if frame.pushed {
+            self.ignores.pop();
+        }
+        if self.mirror() {
+            if let Err(e) = self.prune(&frame.to, &frame.kept) {
+                self.fail(e, &frame.to)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete the entries of the destination directory `to` not in `kept`.
+    fn prune(&mut self, to: &Path, kept: &HashSet<OsString>) -> io::Result<()> {
+        count(Io::ReadDir);
+        // Listed before removing anything, so the directory handle isn't
+        // held while whole subtrees are.
+        let entries = match self.options.fs().read_dir(to) {
+            // Not created when only checking.
+            Err(e) if e.kind() == io::ErrorKind::NotFound && self.options.assert_unchanged => {
+                return Ok(())
+            }
+            entries => entries.context(Operation::ReadDir, Side::Destination, to)?,
+        };
+        for path in entries {
+            if path.file_name().is_some_and(|name| kept.contains(name)) {
+                continue;
+            }
+            self.remove(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Create the destination directory `dir` and its missing parents,
+    /// listing them in the stats.
+    fn create_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let missing = self.missing_dirs(dir);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        count(Io::Write);
+        self.options.fs().create_dir_all(dir).context(
+            Operation::CreateDir,
+            Side::Destination,
+            dir,
+        )?;
+        for created in missing.into_iter().rev() {
+            let created = self.reported(created);
+            self.stats.created_dirs.push(created);
+        }
+        Ok(())
+    }
+
+    /// [`create_dir`](Self::create_dir), or only report `dir` and its missing
+    /// parents as changed under [`DiffOptions::assert_unchanged`], for diffs
+    /// listing files path by path.
+    fn ensure_dir(&mut self, dir: &Path) -> io::Result<()> {
+        if !self.options.assert_unchanged {
+            return self.create_dir(dir);
+        }
+        let missing = self.missing_dirs(dir);
+        for missing in missing.into_iter().rev() {
+            let path = self.reported(missing);
+            if !self.changed.contains(&path) {
+                self.changed.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// `dir` and its ancestors that don't exist, children first.
+    fn missing_dirs<'p>(&self, dir: &'p Path) -> Vec<&'p Path> {
+        let fs = self.options.fs();
+        let mut missing = Vec::new();
+        for ancestor in dir.ancestors() {
+            count(Io::Metadata);
+            if ancestor.as_os_str().is_empty()
+                || timed(Phase::Metadata, || fs.metadata(ancestor)).is_ok()
+            {
+                break;
+            }
+            missing.push(ancestor);
+        }
+        missing
+    }
+
+    /// Delete the destination entry `path` unless it's protected.
+    fn remove(&mut self, path: &Path) -> io::Result<()> {
+        if let Some(protect) = &mut self.options.protect {
+            let rel = path.strip_prefix(self.to_root).unwrap_or(path);
+            if protect(rel) {
+                return Ok(());
+            }
+        }
+        if self.options.assert_unchanged {
+            let path = self.reported(path);
+            self.changed.push(path);
+            return Ok(());
+        }
+        count(Io::Write);
+        let removed = self.options.fs().remove(path);
+        match removed.context(Operation::Remove, Side::Destination, path) {
+            Ok(()) => {
+                let path = self.reported(path);
+                self.stats.deleted.push(path);
+                Ok(())
+            }
+            Err(e) => self.fail(e, path),
+        }
+    }
+
+    /// Diff the single source file `from` given as changed, deleting its
+    /// destination when mirroring if it's gone.
+    fn changed<H: Hasher + Default>(
+        &mut self,
+        from: &Path,
+        to: PathBuf,
+        rel: &Path,
+    ) -> io::Result<()> {
+        count(Io::Metadata);
+        match timed(Phase::Metadata, || self.entry_is_dir(from)) {
+            Ok(true) => {
+                let path = from.to_path_buf();
+                return Err(WodError::NotAFile { path, is_dir: true }.into());
+            }
+            Ok(false) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if self.mirror() {
+                    count(Io::Metadata);
+                    if timed(Phase::Metadata, || self.entry_is_dir(&to)).is_ok() {
+                        self.remove(&to)?;
+                    }
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(e).context(Operation::Metadata, Side::Source, from),
+        }
+        if self.options.map_path.is_none() && !self.options.assert_unchanged {
+            if let Some(parent) = to.parent() {
+                self.create_dir(parent)?;
+            }
+        }
+        self.file::<H>(from, to, rel)
+    }
+
+    /// Whether `path` itself, not what it links to, is a directory.
+    fn entry_is_dir(&self, path: &Path) -> io::Result<bool> {
+        if self.options.local() {
+            fs::symlink_metadata(path).map(|metadata| metadata.is_dir())
+        } else {
+            self.options
+                .fs()
+                .metadata(path)
+                .map(|metadata| metadata.is_dir)
+        }
+    }
+
+    /// Diff a single entry, returning the frame to walk next if it's a
+    /// directory.
+    fn entry<H: Hasher + Default>(
+        &mut self,
+        from: &Path,
+        to: PathBuf,
+        rel: PathBuf,
+    ) -> io::Result<Option<Frame>> {
+        count(Io::Metadata);
+        let metadata = timed(Phase::Metadata, || self.options.fs().metadata(from));
+        let is_dir = metadata.as_ref().is_ok_and(|metadata| metadata.is_dir);
+        if is_dir && self.skip.as_deref() == Some(from) {
+            return Ok(None);
+        }
+        #[cfg(feature = "ignore")]
+        if self.ignores.is_ignored(from, is_dir) {
+            return Ok(None);
+        }
+        if let Ok(metadata) = &metadata {
+            let len = metadata.len;
+            let options = &self.options;
+            if !is_dir
+                && (options.min_size.is_some_and(|min| len < min)
+                    || options.max_size.is_some_and(|max| len > max))
+            {
+                self.record(&to, WriteOutcome::Skipped(SkipReason::Size));
+                return Ok(None);
+            }
+        }
+        if self.options.local() && metadata.is_err_and(|e| e.kind() == io::ErrorKind::NotFound) {
+            count(Io::Metadata);
+            if timed(Phase::Metadata, || fs::symlink_metadata(from))
+                .is_ok_and(|metadata| metadata.file_type().is_symlink())
+            {
+                return self.broken_symlink(from, &to).map(|_| None);
+            }
+        }
+        if is_dir {
+            self.open(from.to_path_buf(), to, rel).map(Some)
+        } else {
+            let limit = self.options.file_limit.clone();
+            let _permit = limit.as_ref().map(FileLimit::acquire);
+            self.file::<H>(from, to, &rel).map(|_| None)
+        }
+    }
+
+    /// Handle the source symlink `from` pointing to nothing as asked by
+    /// [`DiffOptions::broken_symlinks`].
+    fn broken_symlink(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let outcome = match self.options.broken_symlinks {
+            BrokenSymlinks::Skip => WriteOutcome::Skipped(SkipReason::BrokenSymlink),
+            BrokenSymlinks::Error => {
+                let error = io::Error::new(io::ErrorKind::NotFound, "broken symlink");
+                return Err(error).context(Operation::Metadata, Side::Source, from);
+            }
+            BrokenSymlinks::Recreate => {
+                count(Io::Read);
+                let target = fs::read_link(from).context(Operation::Read, Side::Source, from)?;
+                count(Io::Metadata);
+                let outcome = match timed(Phase::Metadata, || fs::symlink_metadata(to)) {
+                    Ok(_) if fs::read_link(to).is_ok_and(|existing| existing == target) => {
+                        WriteOutcome::Skipped(SkipReason::BrokenSymlink)
+                    }
+                    Ok(_) => {
+                        ensure_writable(to, self.options)?;
+                        count(Io::Write);
+                        fs::remove_file(to).context(Operation::Write, Side::Destination, to)?;
+                        WriteOutcome::Overwritten
+                    }
+                    Err(_) => {
+                        ensure_writable(to, self.options)?;
+                        WriteOutcome::Created
+                    }
+                };
+                if outcome != WriteOutcome::Skipped(SkipReason::BrokenSymlink) {
+                    count(Io::Write);
+                    symlink(&target, to).context(Operation::Write, Side::Destination, to)?;
+                }
+                outcome
+            }
+        };
+        self.record(to, outcome);
+        Ok(())
+    }
+
+    fn file<H: Hasher + Default>(
+        &mut self,
+        from: &Path,
+        to: PathBuf,
+        rel: &Path,
+    ) -> io::Result<()> {
+        let to = match &mut self.options.map_path {
+            Some(map_path) => {
+                let to = self.to_root.join(map_path(rel));
+                if let Some(parent) = to.parent().filter(|_| !self.options.assert_unchanged) {
+                    self.create_dir(parent)?;
+                }
+                to
+            }
+            None => to,
+        };
+        #[cfg(feature = "blake3")]
+        {
+            self.options.b3sum = None;
+        }
+        let options = &mut *self.options;
+        if self
+            .journal
+            .as_ref()
+            .is_some_and(|journal| journal.contains(&to))
+        {
+            #[cfg(feature = "blake3")]
+            self.record_digest(from, &to)?;
+            let reason = SkipReason::Resumed;
+            self.record(&to, WriteOutcome::Skipped(reason));
+            return Ok(());
+        }
+        let dedup = options.dedup
+            && options.local()
+            && options.text_for(&to).is_none()
+            && options.compare.is_none()
+            && options.codec(&to).is_none()
+            && options.crypt.is_none();
+        let from_hash = if dedup {
+            let hash =
+                hash_file::<H>(from, options).context(Operation::Read, Side::Source, from)?;
+            Some(hash)
+        } else {
+            None
+        };
+        let linked = match from_hash.and_then(|hash| self.links.get(&hash)) {
+            Some(first) if same_file(first, &to) => {
+                Some(WriteOutcome::Skipped(SkipReason::SameInode))
+            }
+            Some(first) => Self::link_if_absent(from, first, &to, options)?,
+            None => None,
+        };
+        let outcome = match linked {
+            Some(outcome) => outcome,
+            None => {
+                count(Io::Metadata);
+                let outcome = if timed(Phase::Metadata, || options.fs().metadata(&to)).is_ok()
+                    || options.text_for(&to).is_some()
+                    || options.codec(&to).is_some()
+                    || options.crypt.is_some()
+                    || options.sidecar.is_some()
+                    || options.on_hash.is_some()
+                    || digest::enabled(options)
+                {
+                    write_on_file_diff_with_options::<H>(from, &to, options)?
+                } else {
+                    ensure_writable(&to, options)?;
+                    Source::File(from)
+                        .write_to(&to, options)
+                        .counterpart(Some(from), &to)?;
+                    finish_write(Some(from), &to, options).counterpart(Some(from), &to)?;
+                    WriteOutcome::Created
+                };
+                if let Some(hash) = from_hash {
+                    if matches!(
+                        outcome,
+                        WriteOutcome::Created
+                            | WriteOutcome::Overwritten
+                            | WriteOutcome::Skipped(
+                                SkipReason::HashMatch
+                                    | SkipReason::SizeAndHashMatch
+                                    | SkipReason::BothEmpty
+                                    | SkipReason::SameInode
+                            )
+                    ) {
+                        self.links.entry(hash).or_insert_with(|| to.clone());
+                    }
+                }
+                outcome
+            }
+        };
+        #[cfg(feature = "blake3")]
+        self.record_digest(from, &to)?;
+        self.record(&to, outcome);
+        match &mut self.journal {
+            Some(journal) => journal.record(&to),
+            None => Ok(()),
+        }
+    }
+
+    /// Hardlink the missing destination `to` to `first`, which holds content
+    /// hashing like `from`. Returns `None` when it should be copied instead.
+    fn link_if_absent(
+        from: &Path,
+        first: &Path,
+        to: &Path,
+        options: &mut DiffOptions<'_>,
+    ) -> io::Result<Option<WriteOutcome>> {
+        count(Io::Metadata);
+        if timed(Phase::Metadata, || to.exists()) {
+            return Ok(None);
+        }
+        if options.verify_on_match != VerifyOnMatch::Off {
+            count(Io::Open);
+            count(Io::Read);
+            let first_file =
+                File::open(first).context(Operation::Read, Side::Destination, first)?;
+            let from = Source::File(from).reader(options.fs())?;
+            if !same_content(from, BufReader::new(first_file))? {
+                options.hash_collisions += 1;
+                return Ok(None);
+            }
+        }
+        ensure_writable(to, options)?;
+        count(Io::Write);
+        match fs::hard_link(first, to) {
+            Ok(()) => Ok(Some(WriteOutcome::Created)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// List the digest of `from` for [`DiffOptions::b3sums`].
+    #[cfg(feature = "blake3")]
+    fn record_digest(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.options.b3sums {
+            let digest = digest::take(from, self.options)?;
+            let rel = to.strip_prefix(self.to_root).unwrap_or(to);
+            self.stats.b3sums.push((rel.to_path_buf(), digest));
+        }
+        Ok(())
+    }
+
+    /// `path` as listed in the stats, see [`DiffOptions::relative_paths`].
+    fn reported(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(self.to_root) {
+            Ok(rel) if self.options.relative_paths => rel.to_path_buf(),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    fn record(&mut self, to: &Path, outcome: WriteOutcome) {
+        if self.options.max_bytes_written.is_some()
+            && matches!(outcome, WriteOutcome::Created | WriteOutcome::Overwritten)
+        {
+            count(Io::Metadata);
+            let len = timed(Phase::Metadata, || self.options.fs().metadata(to));
+            self.bytes_written += len.map_or(0, |metadata| metadata.len);
+        }
+        if let Some(on_write) = &mut self.options.on_write {
+            let rel = to.strip_prefix(self.to_root).unwrap_or(to);
+            on_write(rel, to, outcome);
+        }
+        let to = self.reported(to);
+        if self.options.collect_files
+            && !matches!(
+                outcome,
+                WriteOutcome::Skipped(SkipReason::CaseCollision | SkipReason::Locked)
+            )
+        {
+            self.stats.files.push(to.clone());
+        }
+        self.stats.record(&to, outcome);
+    }
+
+    /// Record `error` and move on to the next entry if asked to.
+    fn fail(&mut self, error: io::Error, path: &Path) -> io::Result<()> {
+        if self.options.skip_locked && RetryPolicy::is_locked(&error) {
+            let to = DiffError::from_io(&error).map_or(path, |e| &e.path);
+            let to = to.to_path_buf();
+            self.record(&to, WriteOutcome::Skipped(SkipReason::Locked));
+            return Ok(());
+        }
+        if let Some(WodError::Changed { paths }) = WodError::from_io(&error) {
+            if self.options.assert_unchanged {
+                let paths: Vec<_> = paths.iter().map(|path| self.reported(path)).collect();
+                self.changed.extend(paths);
+                return Ok(());
+            }
+        }
+        let aborted = matches!(WodError::from_io(&error), Some(WodError::Aborted { .. }));
+        if !self.options.continue_on_error || aborted {
+            return Err(error);
+        }
+        let error = DiffError::from_io_or_compare(error, path);
+        self.stats.errors.push(error);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use std::fs;
     use std::io::Write;
+    use std::sync::atomic::AtomicBool;
+    use std::time::{Duration, SystemTime};
     use tempfile::tempdir;
     use tempfile::NamedTempFile;
 
     #[test]
-    fn test_dir_diff() -> io::Result<()> {
-        let from_dir = tempdir()?;
+    fn test_dir_diff() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        // Create a file in the source directory
+        let from_file_path = from_dir.path().join("a.txt");
+        let mut from_file = File::create(&from_file_path)?;
+        write!(from_file, "hello")?;
+
+        // Create a file with the same content in the destination directory
+        let to_file_path = to_dir.path().join("a.txt");
+        let mut to_file = File::create(&to_file_path)?;
+        write!(to_file, "hello")?;
+
+        // Create a file with different content in the destination directory
+        let to_file_path_b = to_dir.path().join("b.txt");
+        let mut to_file_b = File::create(&to_file_path_b)?;
+        write!(to_file_b, "world")?;
+
+        // Create a corresponding file in the source directory
+        let from_file_path_b = from_dir.path().join("b.txt");
+        let mut from_file_b = File::create(&from_file_path_b)?;
+        write!(from_file_b, "rust")?;
+
+        // Create a subdirectory and a file in it
+        let from_subdir = from_dir.path().join("sub");
+        fs::create_dir(&from_subdir)?;
+        let from_file_path_c = from_subdir.join("c.txt");
+        let mut from_file_c = File::create(&from_file_path_c)?;
+        write!(from_file_c, "subdir file")?;
+
+        let original_meta = fs::metadata(&to_file_path)?;
+
+        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+
+        // a.txt should not be modified
+        let new_meta = fs::metadata(&to_file_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+
+        // b.txt should be modified
+        let b_content = fs::read_to_string(&to_file_path_b)?;
+        assert_eq!(b_content, "rust");
+
+        // c.txt should be created
+        let c_content = fs::read_to_string(to_dir.path().join("sub/c.txt"))?;
+        assert_eq!(c_content, "subdir file");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_to_nonexistent() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("nonexistent");
+
+        let from_file_path = from_dir.path().join("a.txt");
+        let mut from_file = File::create(&from_file_path)?;
+        write!(from_file, "hello")?;
+
+        write_on_dir_diff::<FxHasher>(from_dir.path(), &to_path)?;
+
+        assert!(to_path.exists());
+        assert!(to_path.is_dir());
+        let to_file_path = to_path.join("a.txt");
+        assert!(to_file_path.exists());
+        let content = fs::read_to_string(to_file_path)?;
+        assert_eq!(content, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_from_empty() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        let to_file_path = to_dir.path().join("a.txt");
+        let mut to_file = File::create(&to_file_path)?;
+        write!(to_file, "hello")?;
+
+        let original_meta = fs::metadata(&to_file_path)?;
+
+        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+
+        let new_meta = fs::metadata(&to_file_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_to_has_extra_files() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        let from_file_path = from_dir.path().join("a.txt");
+        let mut from_file = File::create(&from_file_path)?;
+        write!(from_file, "hello")?;
+
+        let to_extra_file_path = to_dir.path().join("extra.txt");
+        let mut to_extra_file = File::create(&to_extra_file_path)?;
+        write!(to_extra_file, "extra")?;
+
+        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+
+        assert!(to_extra_file_path.exists());
+        let content = fs::read_to_string(&to_extra_file_path)?;
+        assert_eq!(content, "extra");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_deeply_nested() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        let from_sub_dir = from_dir.path().join("a/b/c");
+        fs::create_dir_all(&from_sub_dir)?;
+        let from_file_path = from_sub_dir.join("d.txt");
+        let mut from_file = File::create(&from_file_path)?;
+        write!(from_file, "deep")?;
+
+        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+
+        let to_file_path = to_dir.path().join("a/b/c/d.txt");
+        assert!(to_file_path.exists());
+        let content = fs::read_to_string(to_file_path)?;
+        assert_eq!(content, "deep");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_dest_nonexistent() -> io::Result<()> {
+        let mut from_file = NamedTempFile::new()?;
+        write!(from_file, "hello")?;
+
+        let to_path = NamedTempFile::new()?.into_temp_path();
+        fs::remove_file(&to_path)?;
+
+        write_on_file_diff::<FxHasher>(from_file.path(), &to_path)?;
+
+        assert!(to_path.exists());
+        let content = fs::read_to_string(&to_path)?;
+        assert_eq!(content, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_files_are_same() -> io::Result<()> {
+        let mut from_file = NamedTempFile::new()?;
+        write!(from_file, "hello")?;
+
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "hello")?;
+        let to_path = to_file.path();
+        let original_meta = fs::metadata(to_path)?;
+
+        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+
+        let new_meta = fs::metadata(to_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_files_are_different() -> io::Result<()> {
+        let mut from_file = NamedTempFile::new()?;
+        write!(from_file, "hello")?;
+
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "world")?;
+        let to_path = to_file.path();
+
+        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+
+        let to_content = fs::read_to_string(to_path)?;
+        assert_eq!(to_content, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_source_nonexistent() -> io::Result<()> {
+        let from_path = Path::new("nonexistent_source_file");
+        let to_path = NamedTempFile::new()?.into_temp_path();
+
+        let result = write_on_file_diff::<FxHasher>(from_path, &to_path);
+
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_dest_nonexistent() -> io::Result<()> {
+        let from_bytes = b"hello";
+        let to_path = NamedTempFile::new()?.into_temp_path();
+        fs::remove_file(&to_path)?;
+
+        write_on_bytes_diff::<FxHasher>(from_bytes, &to_path)?;
+
+        assert!(to_path.exists());
+        let content = fs::read_to_string(&to_path)?;
+        assert_eq!(content, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_bytes_are_same() -> io::Result<()> {
+        let from_bytes = b"hello";
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "hello")?;
+        let to_path = to_file.path();
+        let original_meta = fs::metadata(to_path)?;
+
+        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+
+        let new_meta = fs::metadata(to_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_bytes_are_different() -> io::Result<()> {
+        let from_bytes = b"hello";
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "world")?;
+        let to_path = to_file.path();
+
+        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+
+        let to_content = fs::read_to_string(to_path)?;
+        assert_eq!(to_content, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_empty_source() -> io::Result<()> {
+        let from_file = NamedTempFile::new()?;
+
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "world")?;
+        let to_path = to_file.path();
+
+        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+
+        let to_content = fs::read_to_string(to_path)?;
+        assert_eq!(to_content, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_empty_dest() -> io::Result<()> {
+        let mut from_file = NamedTempFile::new()?;
+        write!(from_file, "hello")?;
+
+        let to_file = NamedTempFile::new()?;
+        let to_path = to_file.path();
+
+        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+
+        let to_content = fs::read_to_string(to_path)?;
+        assert_eq!(to_content, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_both_empty() -> io::Result<()> {
+        let from_file = NamedTempFile::new()?;
+        let to_file = NamedTempFile::new()?;
+        let to_path = to_file.path();
+        let original_meta = fs::metadata(to_path)?;
+
+        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+
+        let new_meta = fs::metadata(to_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_empty_source() -> io::Result<()> {
+        let from_bytes = b"";
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "world")?;
+        let to_path = to_file.path();
+
+        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+
+        let to_content = fs::read_to_string(to_path)?;
+        assert_eq!(to_content, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_empty_dest() -> io::Result<()> {
+        let from_bytes = b"hello";
+        let to_file = NamedTempFile::new()?;
+        let to_path = to_file.path();
+
+        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+
+        let to_content = fs::read_to_string(to_path)?;
+        assert_eq!(to_content, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_both_empty() -> io::Result<()> {
+        let from_bytes = b"";
+        let to_file = NamedTempFile::new()?;
+        let to_path = to_file.path();
+        let original_meta = fs::metadata(to_path)?;
+
+        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+
+        let new_meta = fs::metadata(to_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_with_retry_source_nonexistent() -> io::Result<()> {
+        let from_path = Path::new("nonexistent_source_file");
+        let to_path = NamedTempFile::new()?.into_temp_path();
+        let mut options = DiffOptions::new().retry(RetryPolicy::new(3, Duration::ZERO));
+
+        let result = write_on_file_diff_with_options::<FxHasher>(from_path, &to_path, &mut options);
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_text_ignores_bom() -> io::Result<()> {
+        let mut to_file = NamedTempFile::new()?;
+        to_file.write_all(b"hello")?;
+        let to_path = to_file.path();
+        let original_meta = fs::metadata(to_path)?;
+        let mut options =
+            DiffOptions::new().text(TextOptions::new().ignore_bom(true).bom(Bom::Emit));
+
+        write_on_bytes_diff_with_options::<FxHasher>(b"\xEF\xBB\xBFhello", to_path, &mut options)?;
+
+        let new_meta = fs::metadata(to_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+        assert_eq!(fs::read(to_path)?, b"hello");
+
+        write_on_bytes_diff_with_options::<FxHasher>(b"rust", to_path, &mut options)?;
+
+        assert_eq!(fs::read(to_path)?, b"\xEF\xBB\xBFrust");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_diff() -> io::Result<()> {
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "hello")?;
+        let to_path = to_file.path();
+        let original_meta = fs::metadata(to_path)?;
+
+        write_on_reader_diff::<FxHasher>(&b"hello"[..], to_path)?;
+
+        let new_meta = fs::metadata(to_path)?;
+        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+
+        write_on_reader_diff::<FxHasher>(&b"world"[..], to_path)?;
+
+        assert_eq!(fs::read_to_string(to_path)?, "world");
+
+        Ok(())
+    }
+
+    /// Every input collides.
+    #[derive(Default)]
+    struct ConstHasher;
+
+    impl Hasher for ConstHasher {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    #[test]
+    fn test_bytes_diff_verify_on_match() -> io::Result<()> {
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "world")?;
+        let to_path = to_file.path();
+
+        write_on_bytes_diff::<ConstHasher>(b"hello", to_path)?;
+        assert_eq!(fs::read_to_string(to_path)?, "world");
+
+        let mut options = DiffOptions::new().verify_on_match(VerifyOnMatch::Error);
+        let err = write_on_bytes_diff_with_options::<ConstHasher>(b"hello", to_path, &mut options)
+            .unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::HashCollision { .. })
+        ));
+        assert_eq!(fs::read_to_string(to_path)?, "world");
+
+        let mut collisions = Vec::new();
+        let mut options = DiffOptions::new()
+            .verify_on_match(VerifyOnMatch::Warn)
+            .on_hash_collision(|to| collisions.push(to.to_path_buf()));
+        write_on_bytes_diff_with_options::<ConstHasher>(b"hello", to_path, &mut options)?;
+        drop(options);
+        assert_eq!(fs::read_to_string(to_path)?, "hello");
+        assert_eq!(collisions, [to_path]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_counts_hash_collisions() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "hello")?;
+        fs::write(from_dir.path().join("b.txt"), "same")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("a.txt"), "world")?;
+        fs::write(to_dir.path().join("b.txt"), "same")?;
+
+        let mut options = DiffOptions::new().verify_on_match(VerifyOnMatch::Warn);
+        let stats = write_on_dir_diff_with_options::<ConstHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!(stats.hash_collisions, 1);
+        assert_eq!(stats.overwritten, 1);
+        assert_eq!(fs::read_to_string(to_dir.path().join("a.txt"))?, "hello");
+
+        let stats = write_on_dir_diff_with_options::<ConstHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!(stats.hash_collisions, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_map_path_flattens() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        let from_sub_dir = from_dir.path().join("a/b");
+        fs::create_dir_all(&from_sub_dir)?;
+        fs::write(from_sub_dir.join("c.txt"), "flat")?;
+        fs::write(from_dir.path().join("d.txt"), "top")?;
+
+        let mut options = DiffOptions::new().map_path(|rel| {
+            let name = rel
+                .iter()
+                .map(|part| part.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("_");
+            PathBuf::from(name)
+        });
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+
+        assert_eq!(fs::read_to_string(to_dir.path().join("a_b_c.txt"))?, "flat");
+        assert_eq!(fs::read_to_string(to_dir.path().join("d.txt"))?, "top");
+        assert!(!to_dir.path().join("a").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_stats() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        fs::write(from_dir.path().join("same.txt"), "same")?;
+        fs::write(to_dir.path().join("same.txt"), "same")?;
+        fs::write(from_dir.path().join("changed.txt"), "new")?;
+        fs::write(to_dir.path().join("changed.txt"), "old")?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/created.txt"), "created")?;
+
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut DiffOptions::new(),
+        )?;
+
+        assert_eq!(stats.created, 1);
+        assert_eq!(stats.overwritten, 1);
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.written(), 2);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "ignore")]
+    #[test]
+    fn test_dir_diff_ignore_files() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        fs::write(from_dir.path().join(".gitignore"), "*.log\ntarget/\n")?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        fs::write(from_dir.path().join("a.log"), "log")?;
+        fs::create_dir_all(from_dir.path().join("target"))?;
+        fs::write(from_dir.path().join("target/out"), "out")?;
+        fs::create_dir_all(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/.ignore"), "!keep.log\n")?;
+        fs::write(from_dir.path().join("sub/keep.log"), "keep")?;
+        fs::write(from_dir.path().join("sub/drop.log"), "drop")?;
+
+        let mut options = DiffOptions::new().ignore_files(true);
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+
+        assert!(to_dir.path().join("a.txt").exists());
+        assert!(!to_dir.path().join("a.log").exists());
+        assert!(!to_dir.path().join("target").exists());
+        assert!(to_dir.path().join("sub/keep.log").exists());
+        assert!(!to_dir.path().join("sub/drop.log").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_writer_matches_bytes_diff() -> io::Result<()> {
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "hello")?;
+
+        let mut hash = HashWriter::new(FxHasher::default());
+        write!(hash, "hel")?;
+        write!(hash, "lo")?;
+        assert_eq!(
+            hash.into_inner().finish(),
+            hash_file::<FxHasher>(to_file.path(), &mut DiffOptions::new())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_writer_ignores_write_boundaries() -> io::Result<()> {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut whole = HashWriter::new(FxHasher::default());
+        whole.write_all(&data)?;
+        let mut pieces = HashWriter::new(FxHasher::default());
+        for piece in data.chunks(1000) {
+            pieces.write_all(piece)?;
+        }
+
+        assert_eq!(whole.finish(), pieces.finish());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_skip_dest_modified_after() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        fs::write(from_dir.path().join("hotfix.txt"), "generated")?;
+        fs::write(from_dir.path().join("stale.txt"), "generated")?;
+        let cutoff = SystemTime::now() - Duration::from_secs(60);
+        fs::write(to_dir.path().join("hotfix.txt"), "manual edit")?;
+        fs::write(to_dir.path().join("stale.txt"), "old")?;
+        File::options()
+            .write(true)
+            .open(to_dir.path().join("stale.txt"))?
+            .set_modified(cutoff - Duration::from_secs(60))?;
+
+        let mut options = DiffOptions::new().skip_dest_modified_after(cutoff);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+
+        assert_eq!(
+            fs::read_to_string(to_dir.path().join("hotfix.txt"))?,
+            "manual edit"
+        );
+        assert_eq!(
+            fs::read_to_string(to_dir.path().join("stale.txt"))?,
+            "generated"
+        );
+        assert_eq!(stats.dest_newer, [to_dir.path().join("hotfix.txt")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_on_conflict() -> io::Result<()> {
+        let mut to_file = NamedTempFile::new()?;
+        write!(to_file, "local edit")?;
+        let to_path = to_file.path();
+        let baseline_hash = hash_reader::<FxHasher>(&b"synced"[..])?;
+
+        let mut conflicts = Vec::new();
+        let mut options = DiffOptions::new()
+            .baseline(|_| Some(baseline_hash))
+            .on_conflict(|conflict| {
+                conflicts.push(conflict.to.to_path_buf());
+                ConflictAction::Skip
+            });
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"upstream", to_path, &mut options)?;
+        drop(options);
+
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::Conflict));
+        assert_eq!(conflicts, [to_path]);
+        assert_eq!(fs::read_to_string(to_path)?, "local edit");
+
+        let mut options = DiffOptions::new()
+            .baseline(|_| Some(baseline_hash))
+            .on_conflict(|_| ConflictAction::Abort);
+        let err = write_on_bytes_diff_with_options::<FxHasher>(b"upstream", to_path, &mut options)
+            .unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::Aborted { .. })
+        ));
+
+        // Only the source changed since the baseline, not a conflict.
+        let mut options = DiffOptions::new()
+            .baseline(|_| Some(hash_reader::<FxHasher>(&b"local edit"[..]).unwrap()))
+            .on_conflict(|_| ConflictAction::Abort);
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"upstream", to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dir_diff_continue_on_error_tags_side() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        std::os::unix::fs::symlink("missing", from_dir.path().join("dangling"))?;
+        fs::write(from_dir.path().join("blocked.txt"), "blocked")?;
+        fs::create_dir(to_dir.path().join("blocked.txt"))?;
+        fs::write(from_dir.path().join("ok.txt"), "ok")?;
+
+        let err = write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path()).unwrap_err();
+        assert!(DiffError::from_io(&err).is_some());
+
+        let mut options = DiffOptions::new()
+            .continue_on_error(true)
+            .broken_symlinks(BrokenSymlinks::Error);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+
+        assert_eq!(fs::read_to_string(to_dir.path().join("ok.txt"))?, "ok");
+        let mut errors: Vec<_> = stats
+            .errors
+            .iter()
+            .map(|e| (e.operation, e.side, e.path.clone()))
+            .collect();
+        errors.sort_by_key(|e| e.1 == Side::Source);
+        assert_eq!(
+            errors,
+            [
+                (
+                    Operation::Copy,
+                    Side::Destination,
+                    to_dir.path().join("blocked.txt")
+                ),
+                (
+                    Operation::Metadata,
+                    Side::Source,
+                    from_dir.path().join("dangling")
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_case_collisions() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        fs::write(from_dir.path().join("ReadMe.txt"), "new")?;
+        fs::write(to_dir.path().join("readme.txt"), "old")?;
+
+        let mut options = DiffOptions::new().case_collisions(CaseCollisions::Error);
+        let err = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::CaseCollision { .. })
+        ));
+
+        let mut options = DiffOptions::new().case_collisions(CaseCollisions::Canonical);
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+        assert_eq!(fs::read_to_string(to_dir.path().join("readme.txt"))?, "new");
+        assert!(!to_dir.path().join("ReadMe.txt").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_needs_write() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("a.txt");
+        let mut hash = HashWriter::new(FxHasher::default());
+        hash.write_all(b"hello")?;
+        let from_hash = hash.finish();
+
+        assert!(needs_write::<FxHasher>(from_hash, &to_path)?);
+        fs::write(&to_path, "world")?;
+        assert!(needs_write::<FxHasher>(from_hash, &to_path)?);
+        fs::write(&to_path, "hello")?;
+        assert!(!needs_write::<FxHasher>(from_hash, &to_path)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_on_progress() -> io::Result<()> {
+        let from_file = NamedTempFile::new()?;
+        let data = vec![7u8; 200_000];
+        fs::write(from_file.path(), &data)?;
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("big.bin");
+
+        let mut progress = Vec::new();
+        let mut options = DiffOptions::new().on_progress(|_, copied, total| {
+            progress.push((copied, total));
+        });
+        write_on_file_diff_with_options::<FxHasher>(from_file.path(), &to_path, &mut options)?;
+        drop(options);
+
+        assert_eq!(fs::read(&to_path)?, data);
+        assert!(progress.len() > 1);
+        assert_eq!(progress.last(), Some(&(200_000, 200_000)));
+        assert!(progress.windows(2).all(|w| w[0].0 < w[1].0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_bytes_diff_fetches_only_when_needed() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("remote.txt");
+        let content = b"remote content".to_vec();
+        let fingerprint = hash_reader::<FxHasher>(&content[..])?;
+
+        let fetched = std::cell::Cell::new(0);
+        let fetch = || {
+            fetched.set(fetched.get() + 1);
+            Ok(content.clone())
+        };
+        write_on_lazy_bytes_diff::<FxHasher>(&to_path, fingerprint, fetch)?;
+        assert_eq!(fs::read(&to_path)?, content);
+        write_on_lazy_bytes_diff::<FxHasher>(&to_path, fingerprint, fetch)?;
+        assert_eq!(fetched.get(), 1);
+
+        fs::write(&to_path, "stale")?;
+        let outcome = write_on_lazy_bytes_diff_with_options::<FxHasher>(
+            &to_path,
+            fingerprint,
+            fetch,
+            &mut DiffOptions::default(),
+        )?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fetched.get(), 2);
+        assert_eq!(fs::read(&to_path)?, content);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dest_symlinks() -> io::Result<()> {
+        let outside = tempdir()?;
+        let target = outside.path().join("target.txt");
+        fs::write(&target, "outside")?;
+        let to_dir = tempdir()?;
+        let link = to_dir.path().join("link.txt");
+        std::os::unix::fs::symlink(&target, &link)?;
+
+        let mut options = DiffOptions::new().dest_symlinks(DestSymlinks::Follow);
+        write_on_bytes_diff_with_options::<FxHasher>(b"followed", &link, &mut options)?;
+        assert_eq!(fs::read_to_string(&target)?, "followed");
+        assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+
+        write_on_bytes_diff::<FxHasher>(b"replaced", &link)?;
+        assert_eq!(fs::read_to_string(&target)?, "followed");
+        assert!(fs::symlink_metadata(&link)?.file_type().is_file());
+        assert_eq!(fs::read_to_string(&link)?, "replaced");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_if_absent() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("config.toml");
+        let mut options = DiffOptions::new().write_mode(WriteMode::CreateIfAbsent);
+
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"default", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Created);
+
+        fs::write(&to_path, "user edit")?;
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"default", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::DestExists));
+        assert_eq!(fs::read_to_string(&to_path)?, "user edit");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "instrument")]
+    #[test]
+    fn test_dir_diff_io_counters() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        fs::write(from_dir.path().join("b.txt"), "b")?;
+        let to_dir = tempdir()?;
+
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut DiffOptions::default(),
+        )?;
+        assert_eq!(stats.io.copies, 2);
+        assert_eq!(stats.io.reads, 0);
+        assert_eq!(stats.io.read_dirs, 1);
+
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut DiffOptions::default(),
+        )?;
+        assert_eq!(stats.io.copies, 0);
+        assert_eq!(stats.io.reads, 4);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_diff_skips_hardlinks() -> io::Result<()> {
+        let dir = tempdir()?;
+        let from_path = dir.path().join("from.txt");
+        let to_path = dir.path().join("to.txt");
+        fs::write(&from_path, "shared")?;
+        fs::hard_link(&from_path, &to_path)?;
+
+        let mut options = DiffOptions::default();
+        let outcome =
+            write_on_file_diff_with_options::<FxHasher>(&from_path, &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SameInode));
+        let outcome =
+            write_on_file_diff_with_options::<FxHasher>(&from_path, &from_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SameInode));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_resumes_from_journal() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        fs::write(from_dir.path().join("b.txt"), "b")?;
+        let to_dir = tempdir()?;
+        let journal_dir = tempdir()?;
+        let journal = journal_dir.path().join("sync.journal");
+
+        // An interrupted run that got a.txt done.
+        fs::write(to_dir.path().join("a.txt"), "a")?;
+        let done = to_dir.path().join("a.txt");
+        fs::write(&journal, format!("wod-journal run-1\n{}\n", done.display()))?;
+
+        let mut options = DiffOptions::new().journal(&journal, "run-1");
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!((stats.created, stats.skipped), (1, 1));
+        assert!(!journal.exists());
+
+        // A journal of another session is ignored.
+        fs::write(&journal, format!("wod-journal run-0\n{}\n", done.display()))?;
+        fs::remove_file(to_dir.path().join("a.txt"))?;
+        let mut options = DiffOptions::new().journal(&journal, "run-2");
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!((stats.created, stats.skipped), (1, 1));
+        assert_eq!(fs::read_to_string(to_dir.path().join("a.txt"))?, "a");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn test_bytes_diff_compressed_dest() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("artifact.txt.gz");
+        let mut options = DiffOptions::new().compressed_dest(true);
+
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"hello", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Created);
+        assert_eq!(
+            Codec::from_path(&to_path)
+                .unwrap()
+                .decode(&fs::read(&to_path)?)?,
+            b"hello"
+        );
+
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"hello", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::HashMatch));
+
+        fs::write(&to_path, "not gzip")?;
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"hello", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_custom_compare() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("data.json");
+        fs::write(&to_path, "{ \"a\": 1 }")?;
+        let strip = |bytes: &[u8]| -> Vec<u8> {
+            bytes
+                .iter()
+                .copied()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect()
+        };
+        let mut options = DiffOptions::new().compare(|from, to| strip(from) == strip(to));
+
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"{\"a\":1}", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::Equivalent));
+        assert_eq!(fs::read_to_string(&to_path)?, "{ \"a\": 1 }");
+
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"{\"a\":2}", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read_to_string(&to_path)?, "{\"a\":2}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_rejects_directory_source() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+
+        let err =
+            write_on_file_diff::<FxHasher>(from_dir.path(), to_dir.path().join("a")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::IsADirectory);
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::NotAFile { is_dir: true, .. })
+        ));
+        assert!(err.to_string().contains("write_on_dir_diff"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "timing")]
+    #[test]
+    fn test_dir_diff_timings() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), vec![1; 1 << 20])?;
+        let to_dir = tempdir()?;
+
+        let mut options = DiffOptions::default();
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert!(stats.timings.copy > Duration::ZERO);
+        assert_eq!(stats.timings.hash, Duration::ZERO);
+
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert!(stats.timings.hash > Duration::ZERO);
+        assert_eq!(stats.timings.copy, Duration::ZERO);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_mirror_into_prefix() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        let to_dir = tempdir()?;
+        let prefix = to_dir.path().join("site");
+        fs::create_dir_all(prefix.join("old"))?;
+        fs::write(prefix.join("stale.txt"), "stale")?;
+        fs::write(to_dir.path().join("outside.txt"), "outside")?;
+
+        let mut options = DiffOptions::new().dest_prefix("site").mirror(true);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+
+        assert_eq!(fs::read_to_string(prefix.join("a.txt"))?, "a");
+        assert!(!prefix.join("old").exists());
+        assert!(!prefix.join("stale.txt").exists());
+        assert!(to_dir.path().join("outside.txt").exists());
+        let mut deleted = stats.deleted;
+        deleted.sort();
+        assert_eq!(deleted, [prefix.join("old"), prefix.join("stale.txt")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_keeps_empty_dest() -> io::Result<()> {
+        let mut from_file = NamedTempFile::new()?;
+        write!(from_file, "hello")?;
+        let empty_file = NamedTempFile::new()?;
+        let to_file = NamedTempFile::new()?;
+        let mut options = DiffOptions::new().treat_empty_dest_as_present(true);
+
+        let outcome = write_on_file_diff_with_options::<FxHasher>(
+            from_file.path(),
+            to_file.path(),
+            &mut options,
+        )?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::EmptyDest));
+        assert_eq!(fs::read_to_string(to_file.path())?, "");
+
+        let outcome = write_on_file_diff_with_options::<FxHasher>(
+            empty_file.path(),
+            to_file.path(),
+            &mut options,
+        )?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::BothEmpty));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_very_deep_tree() -> io::Result<()> {
+        // Stays below PATH_MAX on Linux, each level adding two bytes.
+        const DEPTH: usize = 1500;
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+        let mut deepest = from_dir.path().to_path_buf();
+        for _ in 0..DEPTH {
+            deepest.push("d");
+        }
+        fs::create_dir_all(&deepest)?;
+        fs::write(deepest.join("f"), "deep")?;
+
+        // A small stack would overflow if the walk recursed per level.
+        let (from, to) = (from_dir.path().to_path_buf(), to_dir.path().to_path_buf());
+        let stats = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                write_on_dir_diff_with_options::<FxHasher>(from, to, &mut DiffOptions::default())
+            })?
+            .join()
+            .unwrap()?;
+        assert_eq!(stats.created, 1);
+
+        let mut copied = to_dir.path().to_path_buf();
+        for _ in 0..DEPTH {
+            copied.push("d");
+        }
+        assert_eq!(fs::read_to_string(copied.join("f"))?, "deep");
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_file_diff_sparse() -> io::Result<()> {
+        use std::io::{Seek, SeekFrom};
+        use std::os::unix::fs::MetadataExt;
+
+        const LEN: u64 = 64 << 20;
+        let from_file = NamedTempFile::new()?;
+        let mut file = from_file.as_file();
+        file.set_len(LEN)?;
+        file.seek(SeekFrom::Start(LEN / 2))?;
+        file.write_all(b"data")?;
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("image.raw");
+
+        let mut options = DiffOptions::new().sparse(true);
+        write_on_file_diff_with_options::<FxHasher>(from_file.path(), &to_path, &mut options)?;
+
+        let copied = fs::read(&to_path)?;
+        assert_eq!(copied.len() as u64, LEN);
+        assert_eq!(&copied[LEN as usize / 2..][..4], b"data");
+        assert!(copied[..LEN as usize / 2].iter().all(|&b| b == 0));
+        // Only the written block is allocated, in 512 byte units.
+        assert!(fs::metadata(&to_path)?.blocks() * 512 < LEN / 2);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dir_diff_checks_free_space() -> io::Result<()> {
+        // A sparse source larger than the disk, taking no space itself. The
+        // destination is missing, so the source is copied without hashing.
+        let from_dir = tempdir()?;
+        let huge = File::create(from_dir.path().join("huge"))?;
+        let available = space::available(from_dir.path())?.unwrap();
+        huge.set_len(available + (1 << 30))?;
+        let to_dir = tempdir()?;
+
+        let mut options = DiffOptions::new().check_free_space(true);
+        let err = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::InsufficientSpace { .. })
+        ));
+        assert!(!to_dir.path().join("huge").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_bytes() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("gen.rs");
+
+        let report = upsert_bytes::<FxHasher>(b"fn main() {}", &to_path)?;
+        assert_eq!(report.outcome, WriteOutcome::Created);
+        assert_eq!(report.len, 12);
+        assert!(!needs_write::<FxHasher>(report.hash, &to_path)?);
+
+        let again = upsert_bytes::<FxHasher>(b"fn main() {}", &to_path)?;
+        assert_eq!(
+            again.outcome,
+            WriteOutcome::Skipped(SkipReason::SizeAndHashMatch)
+        );
+        assert_eq!(again.hash, report.hash);
+
+        let changed = upsert_bytes::<FxHasher>(b"fn main() { run() }", &to_path)?;
+        assert_eq!(changed.outcome, WriteOutcome::Overwritten);
+        assert_eq!(
+            changed.hash,
+            hash_reader::<FxHasher>(&b"fn main() { run() }"[..])?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_newest_first() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let now = SystemTime::now();
+        for (name, age) in [("a.txt", 30), ("b.txt", 10), ("c.txt", 20)] {
+            let path = from_dir.path().join(name);
+            fs::write(&path, name)?;
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(now - Duration::from_secs(age))?;
+        }
+        let to_dir = tempdir()?;
+
+        let mut order = Vec::new();
+        let mut options = DiffOptions::new()
+            .entry_order(EntryOrder::NewestFirst)
+            .on_progress(|to, _, _| order.push(to.file_name().unwrap().to_owned()));
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+        drop(options);
+
+        assert_eq!(order, ["b.txt", "c.txt", "a.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_backup_suffix() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("config.toml");
+        let backup_path = to_dir.path().join("config.toml.bak");
+        let mut options = DiffOptions::new().backup(Backup::Suffix(".bak".into()));
+
+        write_on_bytes_diff_with_options::<FxHasher>(b"old", &to_path, &mut options)?;
+        write_on_bytes_diff_with_options::<FxHasher>(b"old", &to_path, &mut options)?;
+        assert!(!backup_path.exists());
+
+        let outcome = write_on_bytes_diff_with_options::<FxHasher>(b"new", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, b"new");
+        assert_eq!(fs::read(&backup_path)?, b"old");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_backup_dir() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/changed.txt"), "new")?;
+        fs::write(from_dir.path().join("created.txt"), "created")?;
+        let to_dir = tempdir()?;
+        fs::create_dir(to_dir.path().join("sub"))?;
+        fs::write(to_dir.path().join("sub/changed.txt"), "old")?;
+        let backup_dir = tempdir()?;
+
+        let mut options = DiffOptions::new().backup(Backup::Dir(backup_dir.path().to_path_buf()));
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+
+        assert_eq!(fs::read(backup_dir.path().join("sub/changed.txt"))?, b"old");
+        assert_eq!(fs::read(to_dir.path().join("sub/changed.txt"))?, b"new");
+        assert!(!backup_dir.path().join("created.txt").exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dir_diff_dedup() -> io::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("a.txt"), "same")?;
+        fs::write(from_dir.path().join("sub/b.txt"), "same")?;
+        fs::write(from_dir.path().join("c.txt"), "other")?;
+        let to_dir = tempdir()?;
+
+        let mut options = DiffOptions::new()
+            .dedup(true)
+            .verify_on_match(VerifyOnMatch::Error);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!(stats.created, 3);
+
+        let a = fs::metadata(to_dir.path().join("a.txt"))?;
+        let b = fs::metadata(to_dir.path().join("sub/b.txt"))?;
+        assert_eq!((a.ino(), a.nlink()), (b.ino(), 2));
+        assert_eq!(fs::metadata(to_dir.path().join("c.txt"))?.nlink(), 1);
+
+        // A changed source replaces its link instead of writing through it.
+        fs::write(from_dir.path().join("a.txt"), "changed")?;
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+        assert_eq!(fs::read(to_dir.path().join("a.txt"))?, b"changed");
+        assert_eq!(fs::read(to_dir.path().join("sub/b.txt"))?, b"same");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_len() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let from_path = from_dir.path().join("from.txt");
+        fs::write(&from_path, "hello")?;
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("to.txt");
+
+        assert_eq!(write_on_file_diff_len::<FxHasher>(&from_path, &to_path)?, 5);
+        assert_eq!(write_on_file_diff_len::<FxHasher>(&from_path, &to_path)?, 0);
+        assert_eq!(write_on_bytes_diff_len::<FxHasher>(b"hello", &to_path)?, 0);
+        assert_eq!(write_on_bytes_diff_len::<FxHasher>(b"hi", &to_path)?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_mirror_protect() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("app.js"), "code")?;
+        let to_dir = tempdir()?;
+        fs::create_dir(to_dir.path().join(".cache"))?;
+        fs::write(to_dir.path().join(".cache/data"), "cached")?;
+        fs::write(to_dir.path().join(".lock"), "")?;
+        fs::write(to_dir.path().join("stale.js"), "old")?;
+
+        let mut options = DiffOptions::new().mirror(true).protect(|path| {
+            path.file_name()
+                .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+        });
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+
+        assert_eq!(stats.deleted, [to_dir.path().join("stale.js")]);
+        assert!(to_dir.path().join(".cache/data").exists());
+        assert!(to_dir.path().join(".lock").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_relative_paths() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/c.txt"), "new")?;
+        let to_dir = tempdir()?;
+        fs::create_dir(to_dir.path().join("sub"))?;
+        fs::write(to_dir.path().join("sub/c.txt"), "edited")?;
+        fs::write(to_dir.path().join("stale.txt"), "")?;
+
+        let mut written = Vec::new();
+        let mut options = DiffOptions::new()
+            .mirror(true)
+            .relative_paths(true)
+            .skip_dest_modified_after(SystemTime::UNIX_EPOCH)
+            .on_write(|rel, to, outcome| written.push((rel.to_owned(), to.to_owned(), outcome)));
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        drop(options);
+
+        assert_eq!(stats.dest_newer, [Path::new("sub/c.txt")]);
+        assert_eq!(stats.deleted, [Path::new("stale.txt")]);
+        assert_eq!(
+            written,
+            [(
+                PathBuf::from("sub/c.txt"),
+                to_dir.path().join("sub/c.txt"),
+                WriteOutcome::Skipped(SkipReason::DestNewer)
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_paths_diff() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/changed.txt"), "new")?;
+        fs::write(from_dir.path().join("untouched.txt"), "not listed")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("removed.txt"), "gone from source")?;
+
+        let paths = [
+            PathBuf::from("sub/changed.txt"),
+            PathBuf::from("removed.txt"),
+        ];
+        let mut options = DiffOptions::new().mirror(true);
+        let stats = write_on_paths_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &paths,
+            &mut options,
+        )?;
+
+        assert_eq!(stats.created, 1);
+        assert_eq!(stats.deleted, [to_dir.path().join("removed.txt")]);
+        assert_eq!(fs::read(to_dir.path().join("sub/changed.txt"))?, b"new");
+        assert!(!to_dir.path().join("untouched.txt").exists());
+
+        let err = write_on_paths_diff::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &[PathBuf::from("sub")],
+        )
+        .unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::NotAFile { is_dir: true, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_skip_reasons() -> io::Result<()> {
+        let dir = tempdir()?;
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        let options = &mut DiffOptions::new();
+
+        fs::write(&a, "")?;
+        fs::write(&b, "")?;
+        let outcome = write_on_file_diff_with_options::<FxHasher>(&a, &b, options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::BothEmpty));
+
+        fs::write(&a, "same")?;
+        fs::write(&b, "same")?;
+        let outcome = write_on_file_diff_with_options::<FxHasher>(&a, &b, options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SizeAndHashMatch));
+
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            fs::hard_link(&a, &link)?;
+            let outcome = write_on_file_diff_with_options::<FxHasher>(&a, &link, options)?;
+            assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SameInode));
+        }
+
+        // Different sizes are written without hashing either side.
+        fs::write(&a, "longer")?;
+        let outcome = write_on_file_diff_with_options::<FxHasher>(&a, &b, options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&b)?, b"longer");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_assert_unchanged() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("new"))?;
+        fs::write(from_dir.path().join("new/a.txt"), "a")?;
+        fs::write(from_dir.path().join("same.txt"), "same")?;
+        fs::write(from_dir.path().join("stale.txt"), "regenerated")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("same.txt"), "same")?;
+        fs::write(to_dir.path().join("stale.txt"), "old")?;
+        fs::write(to_dir.path().join("extra.txt"), "")?;
+
+        let mut options = DiffOptions::new()
+            .assert_unchanged(true)
+            .mirror(true)
+            .relative_paths(true)
+            .entry_order(EntryOrder::Name);
+        let err = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )
+        .unwrap_err();
+
+        let Some(WodError::Changed { paths }) = WodError::from_io(&err) else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(
+            paths,
+            &[
+                Path::new("new"),
+                Path::new("new/a.txt"),
+                Path::new("stale.txt"),
+                Path::new("extra.txt"),
+            ]
+        );
+        assert!(!to_dir.path().join("new").exists());
+        assert_eq!(fs::read(to_dir.path().join("stale.txt"))?, b"old");
+        assert!(to_dir.path().join("extra.txt").exists());
+
+        let to_path = to_dir.path().join("same.txt");
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"same", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SizeAndHashMatch));
+        let err = write_on_bytes_diff_with_options::<FxHasher>(b"other", &to_path, &mut options)
+            .unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::Changed { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_source_changed_during_copy() -> io::Result<()> {
+        let dir = tempdir()?;
+        let from_path = dir.path().join("app.log");
+        let to_path = dir.path().join("copy.log");
+        fs::write(&from_path, "first line\n")?;
+
+        // Append to the source while the first copy is in progress.
+        let appended = std::cell::Cell::new(false);
+        let append = |_: &Path, _, _| {
+            if !appended.replace(true) {
+                let mut file = File::options().append(true).open(&from_path).unwrap();
+                file.write_all(b"second line\n").unwrap();
+            }
+        };
+        let mut options = DiffOptions::new()
+            .on_source_change(SourceChange::Error)
+            .on_progress(append);
+        let err = write_on_file_diff_with_options::<FxHasher>(&from_path, &to_path, &mut options)
+            .unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::SourceChangedDuringCopy { .. })
+        ));
+        drop(options);
+
+        appended.set(false);
+        fs::write(&from_path, "first line\n")?;
+        let mut options = DiffOptions::new()
+            .on_source_change(SourceChange::Retry { attempts: 1 })
+            .on_progress(append);
+        write_on_file_diff_with_options::<FxHasher>(&from_path, &to_path, &mut options)?;
+        assert_eq!(fs::read(&to_path)?, b"first line\nsecond line\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_multi() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let tos = ["a.toml", "b.toml", "c.toml"].map(|name| to_dir.path().join(name));
+        fs::write(&tos[0], "shared")?;
+        fs::write(&tos[1], "outdated")?;
+
+        let outcomes = write_on_bytes_diff_multi::<FxHasher>(b"shared", &tos)?;
+
+        assert_eq!(
+            outcomes,
+            [
+                WriteOutcome::Skipped(SkipReason::HashMatch),
+                WriteOutcome::Overwritten,
+                WriteOutcome::Created,
+            ]
+        );
+        for to in &tos {
+            assert_eq!(fs::read(to)?, b"shared");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_cancel() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(from_dir.path().join(name), name)?;
+        }
+        let to_dir = tempdir()?;
+
+        let cancel = AtomicBool::new(false);
+        let mut options = DiffOptions::new()
+            .entry_order(EntryOrder::Name)
+            .cancel(&cancel)
+            .on_write(|_, _, _| cancel.store(true, atomic::Ordering::Relaxed));
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+
+        assert!(stats.cancelled);
+        assert_eq!(stats.created, 1);
+        assert!(to_dir.path().join("a.txt").exists());
+        assert!(!to_dir.path().join("b.txt").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_sidecar() -> io::Result<()> {
         let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("data.bin");
+        let sidecar_path = to_dir.path().join("data.bin.sha");
+        let mut options = DiffOptions::new().sidecar(|to| to.with_extension("bin.sha"));
 
-        // Create a file in the source directory
-        let from_file_path = from_dir.path().join("a.txt");
-        let mut from_file = File::create(&from_file_path)?;
-        write!(from_file, "hello")?;
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"data", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Created);
+        let recorded = fs::read_to_string(&sidecar_path)?;
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"data", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SizeAndHashMatch));
 
-        // Create a file with the same content in the destination directory
-        let to_file_path = to_dir.path().join("a.txt");
-        let mut to_file = File::create(&to_file_path)?;
-        write!(to_file, "hello")?;
+        // The destination isn't read while the sidecar is valid, even if it lies.
+        fs::write(
+            &sidecar_path,
+            recorded.replacen(&recorded[..16], "0000000000000000", 1),
+        )?;
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"data", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read_to_string(&sidecar_path)?[..16], recorded[..16]);
 
-        // Create a file with different content in the destination directory
-        let to_file_path_b = to_dir.path().join("b.txt");
-        let mut to_file_b = File::create(&to_file_path_b)?;
-        write!(to_file_b, "world")?;
+        // A modified destination makes the sidecar stale.
+        fs::write(&to_path, "edit")?;
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"data", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, b"data");
 
-        // Create a corresponding file in the source directory
-        let from_file_path_b = from_dir.path().join("b.txt");
-        let mut from_file_b = File::create(&from_file_path_b)?;
-        write!(from_file_b, "rust")?;
+        Ok(())
+    }
 
-        // Create a subdirectory and a file in it
-        let from_subdir = from_dir.path().join("sub");
-        fs::create_dir(&from_subdir)?;
-        let from_file_path_c = from_subdir.join("c.txt");
-        let mut from_file_c = File::create(&from_file_path_c)?;
-        write!(from_file_c, "subdir file")?;
+    #[test]
+    fn test_dir_diff_strategy_per_extension() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("main.rs"), "fn main() {}\n")?;
+        fs::write(from_dir.path().join("data.bin"), "fn main() {}\n")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("main.rs"), "fn main() {}\r\n")?;
+        fs::write(to_dir.path().join("data.bin"), "fn main() {}\r\n")?;
 
-        let original_meta = fs::metadata(&to_file_path)?;
+        let text = TextOptions::new().normalize_newlines(true);
+        let mut options = DiffOptions::new().strategy("rs", CompareStrategy::Text(text));
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
 
-        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+        assert_eq!((stats.skipped, stats.overwritten), (1, 1));
+        assert_eq!(
+            fs::read(to_dir.path().join("main.rs"))?,
+            b"fn main() {}\r\n"
+        );
+        assert_eq!(fs::read(to_dir.path().join("data.bin"))?, b"fn main() {}\n");
 
-        // a.txt should not be modified
-        let new_meta = fs::metadata(&to_file_path)?;
-        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+        Ok(())
+    }
 
-        // b.txt should be modified
-        let b_content = fs::read_to_string(&to_file_path_b)?;
-        assert_eq!(b_content, "rust");
+    #[test]
+    fn test_bytes_diff_touch_on_skip() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("out.o");
+        fs::write(&to_path, "object")?;
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&to_path)?
+            .set_modified(old)?;
 
-        // c.txt should be created
-        let c_content = fs::read_to_string(to_dir.path().join("sub/c.txt"))?;
-        assert_eq!(c_content, "subdir file");
+        let mut options = DiffOptions::new().touch_on_skip(true);
+        let outcome =
+            write_on_bytes_diff_with_options::<FxHasher>(b"object", &to_path, &mut options)?;
+
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SizeAndHashMatch));
+        assert!(fs::metadata(&to_path)?.modified()? > old + Duration::from_secs(60));
+        assert_eq!(fs::read(&to_path)?, b"object");
 
         Ok(())
     }
 
     #[test]
-    fn test_dir_diff_to_nonexistent() -> io::Result<()> {
+    fn test_dir_diff_created_dirs() -> io::Result<()> {
         let from_dir = tempdir()?;
+        fs::create_dir_all(from_dir.path().join("a/b"))?;
+        fs::create_dir(from_dir.path().join("old"))?;
+        fs::write(from_dir.path().join("a/b/c.txt"), "c")?;
         let to_dir = tempdir()?;
-        let to_path = to_dir.path().join("nonexistent");
+        let to = to_dir.path().join("new/root");
+        fs::create_dir_all(to.join("old"))?;
 
-        let from_file_path = from_dir.path().join("a.txt");
-        let mut from_file = File::create(&from_file_path)?;
-        write!(from_file, "hello")?;
-
-        write_on_dir_diff::<FxHasher>(from_dir.path(), &to_path)?;
+        let mut options = DiffOptions::new().relative_paths(true);
+        let mut stats =
+            write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), &to, &mut options)?;
+        stats.created_dirs.sort();
+        assert_eq!(stats.created_dirs, [Path::new("a"), Path::new("a/b")]);
 
-        assert!(to_path.exists());
-        assert!(to_path.is_dir());
-        let to_file_path = to_path.join("a.txt");
-        assert!(to_file_path.exists());
-        let content = fs::read_to_string(to_file_path)?;
-        assert_eq!(content, "hello");
+        let to = to_dir.path().join("fresh/root");
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            &to,
+            &mut DiffOptions::new(),
+        )?;
+        assert_eq!(
+            stats.created_dirs[..2],
+            [to_dir.path().join("fresh"), to.clone()]
+        );
 
         Ok(())
     }
 
     #[test]
-    fn test_dir_diff_from_empty() -> io::Result<()> {
+    fn test_dir_diff_into_own_subdir() -> io::Result<()> {
         let from_dir = tempdir()?;
-        let to_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("src"))?;
+        fs::write(from_dir.path().join("src/lib.rs"), "lib")?;
+        let to = from_dir.path().join("output");
 
-        let to_file_path = to_dir.path().join("a.txt");
-        let mut to_file = File::create(&to_file_path)?;
-        write!(to_file, "hello")?;
+        write_on_dir_diff::<FxHasher>(from_dir.path(), &to)?;
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            &to,
+            &mut DiffOptions::new(),
+        )?;
 
-        let original_meta = fs::metadata(&to_file_path)?;
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(fs::read(to.join("src/lib.rs"))?, b"lib");
+        assert!(!to.join("output").exists());
 
-        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+        let err = write_on_dir_diff::<FxHasher>(&to, from_dir.path()).unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::NestedDirs { .. })
+        ));
 
-        let new_meta = fs::metadata(&to_file_path)?;
-        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_diff_two_tier() -> io::Result<()> {
+        let dir = tempdir()?;
+        let from_path = dir.path().join("from");
+        let to_path = dir.path().join("to");
+        let mut content = vec![b'a'; 3 * FINGERPRINT_LEN as usize];
+        fs::write(&from_path, &content)?;
+        *content.last_mut().unwrap() = b'b';
+        fs::write(&to_path, &content)?;
+
+        let mut options = DiffOptions::new().two_tier(true);
+        let outcome =
+            write_on_file_diff_with_options::<FxHasher>(&from_path, &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, fs::read(&from_path)?);
+        assert_eq!(
+            fs::metadata(&to_path)?.modified()?,
+            fs::metadata(&from_path)?.modified()?
+        );
+
+        let outcome =
+            write_on_file_diff_with_options::<FxHasher>(&from_path, &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::SizeAndHashMatch));
 
         Ok(())
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_dir_diff_to_has_extra_files() -> io::Result<()> {
-        let from_dir = tempdir()?;
+    fn test_bytes_diff_preserve_inode() -> io::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
         let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("app.log");
+        fs::write(&to_path, "old")?;
+        let handle = File::open(&to_path)?;
+        let inode = fs::metadata(&to_path)?.ino();
 
-        let from_file_path = from_dir.path().join("a.txt");
-        let mut from_file = File::create(&from_file_path)?;
-        write!(from_file, "hello")?;
+        let mut options = DiffOptions::new()
+            .preserve_inode(true)
+            .backup(Backup::Suffix(".bak".into()));
+        let outcome = write_on_bytes_diff_with_options::<FxHasher>(b"new", &to_path, &mut options)?;
 
-        let to_extra_file_path = to_dir.path().join("extra.txt");
-        let mut to_extra_file = File::create(&to_extra_file_path)?;
-        write!(to_extra_file, "extra")?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::metadata(&to_path)?.ino(), inode);
+        assert_eq!(handle.metadata()?.ino(), inode);
+        assert_eq!(io::read_to_string(handle)?, "new");
+        assert_eq!(fs::read(to_dir.path().join("app.log.bak"))?, b"old");
 
-        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+        Ok(())
+    }
 
-        assert!(to_extra_file_path.exists());
-        let content = fs::read_to_string(&to_extra_file_path)?;
-        assert_eq!(content, "extra");
+    #[test]
+    fn test_dir_diff_max_bytes_written() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(from_dir.path().join(name), "0123456789")?;
+        }
+        let to_dir = tempdir()?;
+
+        let mut options = DiffOptions::new()
+            .entry_order(EntryOrder::Name)
+            .max_bytes_written(15);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert!(stats.quota_reached);
+        assert!(!stats.cancelled);
+        assert_eq!(stats.created, 2);
+        assert!(!to_dir.path().join("c.txt").exists());
+
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert!(!stats.quota_reached);
+        assert_eq!((stats.created, stats.skipped), (1, 2));
 
         Ok(())
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_dir_diff_deeply_nested() -> io::Result<()> {
+    fn test_dir_diff_broken_symlinks() -> io::Result<()> {
         let from_dir = tempdir()?;
+        std::os::unix::fs::symlink("missing", from_dir.path().join("dangling"))?;
+        fs::write(from_dir.path().join("ok.txt"), "ok")?;
         let to_dir = tempdir()?;
+        let link = to_dir.path().join("dangling");
 
-        let from_sub_dir = from_dir.path().join("a/b/c");
-        fs::create_dir_all(&from_sub_dir)?;
-        let from_file_path = from_sub_dir.join("d.txt");
-        let mut from_file = File::create(&from_file_path)?;
-        write!(from_file, "deep")?;
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut DiffOptions::new(),
+        )?;
+        assert_eq!((stats.created, stats.skipped), (1, 1));
+        assert!(fs::symlink_metadata(&link).is_err());
 
-        write_on_dir_diff::<FxHasher>(from_dir.path(), to_dir.path())?;
+        let mut options = DiffOptions::new().broken_symlinks(BrokenSymlinks::Recreate);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!((stats.created, stats.skipped), (1, 1));
+        assert_eq!(fs::read_link(&link)?, Path::new("missing"));
 
-        let to_file_path = to_dir.path().join("a/b/c/d.txt");
-        assert!(to_file_path.exists());
-        let content = fs::read_to_string(to_file_path)?;
-        assert_eq!(content, "deep");
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!((stats.created, stats.skipped), (0, 2));
 
         Ok(())
     }
 
     #[test]
-    fn test_file_diff_dest_nonexistent() -> io::Result<()> {
-        let mut from_file = NamedTempFile::new()?;
-        write!(from_file, "hello")?;
-
-        let to_path = NamedTempFile::new()?.into_temp_path();
-        fs::remove_file(&to_path)?;
+    fn test_bytes_diff_on_similarity() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("table.csv");
+        let old: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("{i},{}\n", i * 7).into_bytes())
+            .collect();
+        fs::write(&to_path, &old)?;
+        let mut new = old.clone();
+        new.extend_from_slice(b"20000,140000\n");
 
-        write_on_file_diff::<FxHasher>(from_file.path(), &to_path)?;
+        let mut reported = Vec::new();
+        let mut options = DiffOptions::new().on_similarity(|to, percent| {
+            reported.push((to.to_owned(), percent));
+        });
+        let outcome = write_on_bytes_diff_with_options::<FxHasher>(&new, &to_path, &mut options)?;
+        write_on_bytes_diff_with_options::<FxHasher>(&new, &to_path, &mut options)?;
+        drop(options);
 
-        assert!(to_path.exists());
-        let content = fs::read_to_string(&to_path)?;
-        assert_eq!(content, "hello");
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].0, to_path);
+        assert!((90..100).contains(&reported[0].1));
+        assert_eq!(fs::read(&to_path)?, new);
 
         Ok(())
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_file_diff_files_are_same() -> io::Result<()> {
-        let mut from_file = NamedTempFile::new()?;
-        write!(from_file, "hello")?;
+    fn test_dir_diff_create_mode() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
 
-        let mut to_file = NamedTempFile::new()?;
-        write!(to_file, "hello")?;
-        let to_path = to_file.path();
-        let original_meta = fs::metadata(to_path)?;
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("key.pem"), "secret")?;
+        fs::set_permissions(
+            from_dir.path().join("key.pem"),
+            fs::Permissions::from_mode(0o644),
+        )?;
+        let to_dir = tempdir()?;
 
-        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+        let mut options = DiffOptions::new().create_mode(0o600);
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+        write_on_bytes_diff_with_options::<FxHasher>(
+            b"token",
+            to_dir.path().join("token"),
+            &mut options,
+        )?;
 
-        let new_meta = fs::metadata(to_path)?;
-        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+        for name in ["key.pem", "token"] {
+            let mode = fs::metadata(to_dir.path().join(name))?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{name}");
+        }
+        assert_eq!(fs::read(to_dir.path().join("key.pem"))?, b"secret");
 
         Ok(())
     }
 
     #[test]
-    fn test_file_diff_files_are_different() -> io::Result<()> {
-        let mut from_file = NamedTempFile::new()?;
-        write!(from_file, "hello")?;
+    fn test_dir_diff_comparator_registry() -> io::Result<()> {
+        fn squeeze(bytes: &[u8]) -> Vec<u8> {
+            bytes
+                .iter()
+                .copied()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect()
+        }
 
-        let mut to_file = NamedTempFile::new()?;
-        write!(to_file, "world")?;
-        let to_path = to_file.path();
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.json"), "{\"a\": 1}")?;
+        fs::write(from_dir.path().join("b.txt"), "b  c")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("a.json"), "{\n  \"a\":1\n}")?;
+        fs::write(to_dir.path().join("b.txt"), "bc")?;
 
-        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+        let registry = ComparatorRegistry::new().register(|from| {
+            from.starts_with(b"{").then(|| {
+                Box::new(|from: &[u8], to: &[u8]| squeeze(from) == squeeze(to))
+                    as Box<dyn Comparator>
+            })
+        });
+        let mut options = DiffOptions::new().comparators(registry);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
 
-        let to_content = fs::read_to_string(to_path)?;
-        assert_eq!(to_content, "hello");
+        assert_eq!((stats.overwritten, stats.skipped), (1, 1));
+        assert_eq!(fs::read(to_dir.path().join("a.json"))?, b"{\n  \"a\":1\n}");
+        assert_eq!(fs::read(to_dir.path().join("b.txt"))?, b"b  c");
 
         Ok(())
     }
 
     #[test]
-    fn test_file_diff_source_nonexistent() -> io::Result<()> {
-        let from_path = Path::new("nonexistent_source_file");
-        let to_path = NamedTempFile::new()?.into_temp_path();
+    fn test_dir_diff_on_finish() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        let to_dir = tempdir()?;
 
-        let result = write_on_file_diff::<FxHasher>(from_path, &to_path);
+        let mut finished = Vec::new();
+        let mut options = DiffOptions::new().on_finish(|stats| finished.push(stats.changed()));
+        for _ in 0..2 {
+            write_on_dir_diff_with_options::<FxHasher>(
+                from_dir.path(),
+                to_dir.path(),
+                &mut options,
+            )?;
+        }
+        drop(options);
 
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(finished, [true, false]);
 
         Ok(())
     }
 
     #[test]
-    fn test_bytes_diff_dest_nonexistent() -> io::Result<()> {
-        let from_bytes = b"hello";
-        let to_path = NamedTempFile::new()?.into_temp_path();
-        fs::remove_file(&to_path)?;
+    fn test_dir_diff_strip_prefix() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir_all(from_dir.path().join("build/dist/js"))?;
+        fs::write(from_dir.path().join("build/dist/js/app.js"), "app")?;
+        fs::write(from_dir.path().join("build/log.txt"), "log")?;
+        let to_dir = tempdir()?;
 
-        write_on_bytes_diff::<FxHasher>(from_bytes, &to_path)?;
+        let mut options = DiffOptions::new()
+            .strip_prefix("build/dist")
+            .dest_prefix("static");
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!(stats.created, 1);
+        assert_eq!(fs::read(to_dir.path().join("static/js/app.js"))?, b"app");
 
-        assert!(to_path.exists());
-        let content = fs::read_to_string(&to_path)?;
-        assert_eq!(content, "hello");
+        fs::write(from_dir.path().join("build/dist/js/app.js"), "app v2")?;
+        let paths = [
+            PathBuf::from("build/dist/js/app.js"),
+            PathBuf::from("build/log.txt"),
+        ];
+        let stats = write_on_paths_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &paths,
+            &mut options,
+        )?;
+        assert_eq!(stats.overwritten, 1);
+        assert_eq!(fs::read(to_dir.path().join("static/js/app.js"))?, b"app v2");
+        assert!(!to_dir.path().join("static/log.txt").exists());
 
         Ok(())
     }
 
     #[test]
-    fn test_bytes_diff_bytes_are_same() -> io::Result<()> {
-        let from_bytes = b"hello";
-        let mut to_file = NamedTempFile::new()?;
-        write!(to_file, "hello")?;
-        let to_path = to_file.path();
-        let original_meta = fs::metadata(to_path)?;
+    fn test_bytes_diff_dest_unreadable() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("notes.txt");
+        // Reading a directory fails whatever the permissions.
+        fs::create_dir(&to_path)?;
 
-        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+        let mut options = DiffOptions::new()
+            .text(TextOptions::new())
+            .dest_unreadable(DestUnreadable::Error);
+        let err = write_on_bytes_diff_with_options::<FxHasher>(b"notes", &to_path, &mut options)
+            .unwrap_err();
 
-        let new_meta = fs::metadata(to_path)?;
-        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+        let Some(WodError::DestinationUnreadable { path, .. }) = WodError::from_io(&err) else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(path, &to_path);
+        assert!(to_path.is_dir());
 
         Ok(())
     }
 
     #[test]
-    fn test_bytes_diff_bytes_are_different() -> io::Result<()> {
-        let from_bytes = b"hello";
-        let mut to_file = NamedTempFile::new()?;
-        write!(to_file, "world")?;
-        let to_path = to_file.path();
+    fn test_dir_diff_on_hash() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("new.txt"), "new")?;
+        fs::write(from_dir.path().join("resized.txt"), "longer")?;
+        fs::write(from_dir.path().join("same.txt"), "same")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("resized.txt"), "short")?;
+        fs::write(to_dir.path().join("same.txt"), "same")?;
 
-        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+        let mut hashes = Vec::new();
+        let mut options = DiffOptions::new()
+            .entry_order(EntryOrder::Name)
+            .on_hash(|to, hash| hashes.push((to.to_owned(), hash)));
+        write_on_dir_diff_with_options::<FxHasher>(from_dir.path(), to_dir.path(), &mut options)?;
+        drop(options);
 
-        let to_content = fs::read_to_string(to_path)?;
-        assert_eq!(to_content, "hello");
+        assert_eq!(
+            hashes,
+            [
+                (
+                    to_dir.path().join("new.txt"),
+                    hash_reader::<FxHasher>(&b"new"[..])?
+                ),
+                (
+                    to_dir.path().join("resized.txt"),
+                    hash_reader::<FxHasher>(&b"longer"[..])?
+                ),
+            ]
+        );
+        assert_eq!(fs::read(to_dir.path().join("new.txt"))?, b"new");
 
         Ok(())
     }
 
     #[test]
-    fn test_file_diff_empty_source() -> io::Result<()> {
-        let from_file = NamedTempFile::new()?;
+    fn test_file_diff_sample() -> io::Result<()> {
+        let dir = tempdir()?;
+        let from_path = dir.path().join("from");
+        let to_path = dir.path().join("to");
+        let mut content = vec![b'a'; 64 * 1024];
+        fs::write(&from_path, &content)?;
+        content[32 * 1024] = b'b';
+        fs::write(&to_path, &content)?;
 
-        let mut to_file = NamedTempFile::new()?;
-        write!(to_file, "world")?;
-        let to_path = to_file.path();
+        let mut options = DiffOptions::new().sample(SampleSpec {
+            head: 1024,
+            tail: 1024,
+        });
+        let outcome =
+            write_on_file_diff_with_options::<FxHasher>(&from_path, &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::Sampled));
+        assert_eq!(fs::read(&to_path)?, content);
 
-        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+        *content.last_mut().unwrap() = b'b';
+        fs::write(&to_path, &content)?;
+        let outcome =
+            write_on_file_diff_with_options::<FxHasher>(&from_path, &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, fs::read(&from_path)?);
 
-        let to_content = fs::read_to_string(to_path)?;
-        assert_eq!(to_content, "");
+        let outcome = write_on_bytes_diff_with_options::<FxHasher>(b"abc", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        let outcome = write_on_bytes_diff_with_options::<FxHasher>(b"abc", &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::Sampled));
 
         Ok(())
     }
 
     #[test]
-    fn test_file_diff_empty_dest() -> io::Result<()> {
-        let mut from_file = NamedTempFile::new()?;
-        write!(from_file, "hello")?;
-
-        let to_file = NamedTempFile::new()?;
-        let to_path = to_file.path();
+    fn test_dir_diff_collect_files() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        fs::write(from_dir.path().join("sub/b.txt"), "b")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("a.txt"), "a")?;
 
-        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+        let mut options = DiffOptions::new().collect_files(true).relative_paths(true);
+        let mut stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        stats.files.sort();
+        assert_eq!(stats.files, [Path::new("a.txt"), Path::new("sub/b.txt")]);
+        assert_eq!((stats.written(), stats.skipped), (1, 1));
 
-        let to_content = fs::read_to_string(to_path)?;
-        assert_eq!(to_content, "hello");
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut DiffOptions::new(),
+        )?;
+        assert!(stats.files.is_empty());
 
         Ok(())
     }
 
     #[test]
-    fn test_file_diff_both_empty() -> io::Result<()> {
-        let from_file = NamedTempFile::new()?;
-        let to_file = NamedTempFile::new()?;
-        let to_path = to_file.path();
-        let original_meta = fs::metadata(to_path)?;
+    fn test_dir_diff_encrypted_dest() -> io::Result<()> {
+        use std::cell::Cell;
 
-        write_on_file_diff::<FxHasher>(from_file.path(), to_path)?;
+        // A toy cipher with a fresh nonce per encryption.
+        let nonce = Cell::new(0u8);
+        let encrypt = |_: &Path, plain: &[u8]| -> io::Result<Vec<u8>> {
+            nonce.set(nonce.get() + 1);
+            let n = nonce.get();
+            Ok([n].into_iter().chain(plain.iter().map(|b| b ^ n)).collect())
+        };
+        let decrypt = |_: &Path, cipher: &[u8]| -> io::Result<Vec<u8>> {
+            let (&n, rest) = cipher.split_first().ok_or(io::ErrorKind::InvalidData)?;
+            Ok(rest.iter().map(|b| b ^ n).collect())
+        };
 
-        let new_meta = fs::metadata(to_path)?;
-        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "secret")?;
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("a.txt");
+
+        let mut options = DiffOptions::new().encrypted_dest(decrypt, encrypt);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!(stats.created, 1);
+        let cipher = fs::read(&to_path)?;
+        assert_ne!(cipher, b"secret");
+
+        // Re-encrypted with another nonce, the plaintext is the same.
+        fs::write(
+            &to_path,
+            [7].into_iter()
+                .chain(b"secret".iter().map(|b| b ^ 7))
+                .collect::<Vec<_>>(),
+        )?;
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!((stats.written(), stats.skipped), (0, 1));
+
+        fs::write(from_dir.path().join("a.txt"), "changed")?;
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!(stats.overwritten, 1);
+        drop(options);
+        assert_eq!(decrypt(&to_path, &fs::read(&to_path)?)?, b"changed");
 
         Ok(())
     }
 
     #[test]
-    fn test_bytes_diff_empty_source() -> io::Result<()> {
-        let from_bytes = b"";
-        let mut to_file = NamedTempFile::new()?;
-        write!(to_file, "world")?;
-        let to_path = to_file.path();
+    fn test_dir_diff_file_limit() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        fs::write(from_dir.path().join("sub/b.txt"), "b")?;
 
-        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+        let limit = FileLimit::new(2);
+        let diffs: Vec<_> = (0..4)
+            .map(|_| {
+                let from = from_dir.path().to_path_buf();
+                let limit = limit.clone();
+                std::thread::spawn(move || -> io::Result<usize> {
+                    let to_dir = tempdir()?;
+                    let mut options = DiffOptions::new().file_limit(limit);
+                    let stats = write_on_dir_diff_with_options::<FxHasher>(
+                        &from,
+                        to_dir.path(),
+                        &mut options,
+                    )?;
+                    Ok(stats.created)
+                })
+            })
+            .collect();
+        for diff in diffs {
+            assert_eq!(diff.join().unwrap()?, 2);
+        }
 
-        let to_content = fs::read_to_string(to_path)?;
-        assert_eq!(to_content, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_diff_canonicalize() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("version.rs");
+        fs::write(&to_path, "// built 2024-01-01\nconst V: u32 = 1;\n")?;
+
+        // Drop the first line holding the build date.
+        let mut options = DiffOptions::new().canonicalize(|bytes| {
+            let start = bytes.iter().position(|&b| b == b'\n').map_or(0, |i| i + 1);
+            Cow::Borrowed(&bytes[start..])
+        });
+        let outcome = write_on_bytes_diff_with_options::<FxHasher>(
+            b"// built 2024-06-30\nconst V: u32 = 1;\n",
+            &to_path,
+            &mut options,
+        )?;
+        assert_eq!(outcome, WriteOutcome::Skipped(SkipReason::HashMatch));
+
+        let source = b"// built 2024-06-30\nconst V: u32 = 2;\n";
+        let outcome = write_on_bytes_diff_with_options::<FxHasher>(source, &to_path, &mut options)?;
+        assert_eq!(outcome, WriteOutcome::Overwritten);
+        assert_eq!(fs::read(&to_path)?, source);
 
         Ok(())
     }
 
     #[test]
-    fn test_bytes_diff_empty_dest() -> io::Result<()> {
-        let from_bytes = b"hello";
-        let to_file = NamedTempFile::new()?;
-        let to_path = to_file.path();
+    fn test_file_diff_error_has_both_paths() -> io::Result<()> {
+        let dir = tempdir()?;
+        let from_path = dir.path().join("from.txt");
+        fs::write(&from_path, "content")?;
+        fs::write(dir.path().join("file"), "")?;
+        // The parent of the destination is a file.
+        let to_path = dir.path().join("file/to.txt");
 
-        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+        let err = write_on_file_diff::<FxHasher>(&from_path, &to_path).unwrap_err();
+        let error = DiffError::from_io(&err).unwrap();
+        assert_eq!(error.side, Side::Destination);
+        assert_eq!(error.path, to_path);
+        assert_eq!(error.counterpart.as_deref(), Some(&*from_path));
+        let message = err.to_string();
+        assert!(message.contains(&*to_path.to_string_lossy()));
+        assert!(message.contains(&format!("(source {})", from_path.display())));
 
-        let to_content = fs::read_to_string(to_path)?;
-        assert_eq!(to_content, "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_size_range() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("empty.cfg"), "")?;
+        fs::write(from_dir.path().join("app.cfg"), "key = 1")?;
+        fs::write(from_dir.path().join("video.mp4"), vec![0; 4096])?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("video.mp4"), "old")?;
+
+        let mut written = Vec::new();
+        let mut options = DiffOptions::new()
+            .min_size(1)
+            .max_size(1024)
+            .mirror(true)
+            .on_write(|rel, _, outcome| written.push((rel.to_owned(), outcome)));
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        drop(options);
+
+        assert_eq!((stats.created, stats.skipped), (1, 2));
+        assert!(stats.deleted.is_empty());
+        assert_eq!(fs::read(to_dir.path().join("video.mp4"))?, b"old");
+        assert!(!to_dir.path().join("empty.cfg").exists());
+        written.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            written,
+            [
+                (PathBuf::from("app.cfg"), WriteOutcome::Created),
+                (
+                    PathBuf::from("empty.cfg"),
+                    WriteOutcome::Skipped(SkipReason::Size)
+                ),
+                (
+                    PathBuf::from("video.mp4"),
+                    WriteOutcome::Skipped(SkipReason::Size)
+                ),
+            ]
+        );
 
         Ok(())
     }
 
     #[test]
-    fn test_bytes_diff_both_empty() -> io::Result<()> {
-        let from_bytes = b"";
-        let to_file = NamedTempFile::new()?;
-        let to_path = to_file.path();
-        let original_meta = fs::metadata(to_path)?;
+    fn test_lazy_bytes_diff_dest_unreadable() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("blob");
+        fs::create_dir(&to_path)?;
+        let fingerprint = hash_reader::<FxHasher>(&b"blob"[..])?;
 
-        write_on_bytes_diff::<FxHasher>(from_bytes, to_path)?;
+        let mut options = DiffOptions::new().dest_unreadable(DestUnreadable::Error);
+        let err = write_on_lazy_bytes_diff_with_options::<FxHasher>(
+            &to_path,
+            fingerprint,
+            || Ok(b"blob".to_vec()),
+            &mut options,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            WodError::from_io(&err),
+            Some(WodError::DestinationUnreadable { .. })
+        ));
 
-        let new_meta = fs::metadata(to_path)?;
-        assert_eq!(original_meta.modified()?, new_meta.modified()?);
+        // Overwriting is attempted, failing on a directory.
+        let err = write_on_lazy_bytes_diff_with_options::<FxHasher>(
+            &to_path,
+            fingerprint,
+            || Ok(b"blob".to_vec()),
+            &mut DiffOptions::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            DiffError::from_io(&err).unwrap().operation,
+            Operation::Write
+        );
 
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,74 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A budget of files open at once, shared by the diffs given it through
+/// [`DiffOptions::file_limit`](crate::DiffOptions::file_limit), e.g. to run
+/// several of them in threads under a low `ulimit -n`.
+///
+/// Each file being diffed counts as two, for its source and destination.
+/// Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct FileLimit {
+    max_open: usize,
+    open: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl FileLimit {
+    /// Allow `max_open` files open at once, at least one file diffed at a
+    /// time whatever it is.
+    pub fn new(max_open: usize) -> Self {
+        Self {
+            max_open: max_open.max(1),
+            open: Arc::default(),
+        }
+    }
+
+    /// Wait until a file can be diffed, the returned permit giving its slots
+    /// back when dropped.
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let slots = self.max_open.min(2);
+        let (open, freed) = &*self.open;
+        let mut open = open.lock().unwrap_or_else(|e| e.into_inner());
+        while *open + slots > self.max_open {
+            open = freed.wait(open).unwrap_or_else(|e| e.into_inner());
+        }
+        *open += slots;
+        Permit { limit: self, slots }
+    }
+}
+
+pub(crate) struct Permit<'l> {
+    limit: &'l FileLimit,
+    slots: usize,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let (open, freed) = &*self.limit.open;
+        *open.lock().unwrap_or_else(|e| e.into_inner()) -= self.slots;
+        freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread, time::Duration};
+
+    #[test]
+    fn test_file_limit_waits_for_slots() {
+        let limit = FileLimit::new(3);
+        let permit = limit.acquire();
+
+        let (sender, receiver) = mpsc::channel();
+        let other = limit.clone();
+        let waiter = thread::spawn(move || {
+            let _permit = other.acquire();
+            sender.send(()).unwrap();
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(permit);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        waiter.join().unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fmt,
+    hash::Hasher,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diff_into, ensure_relative, error::Context, memdir::Kept, write_on_bytes_diff_with_options,
+    write_on_lazy_bytes_diff_with_options, DiffOptions, DirDiffStats, Operation, Side,
+};
+
+/// Opens the content of a listed file.
+type Content<'a> = Box<dyn FnMut() -> io::Result<Box<dyn Read + 'a>> + 'a>;
+
+/// What a [`ListedEntry`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+}
+
+/// An entry of a source tree enumerated by the caller, e.g. from a database,
+/// for [`write_on_listing_diff`].
+pub struct ListedEntry<'a> {
+    path: PathBuf,
+    kind: EntryKind,
+    hash: Option<u64>,
+    content: Option<Content<'a>>,
+}
+
+impl<'a> ListedEntry<'a> {
+    /// A file at `path`, relative to the destination root, whose content
+    /// `content` opens.
+    pub fn file(
+        path: impl Into<PathBuf>,
+        content: impl FnMut() -> io::Result<Box<dyn Read + 'a>> + 'a,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            kind: EntryKind::File,
+            hash: None,
+            content: Some(Box::new(content)),
+        }
+    }
+
+    /// A directory at `path`, relative to the destination root, created even
+    /// if no file is listed below it.
+    pub fn dir(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            kind: EntryKind::Dir,
+            hash: None,
+            content: None,
+        }
+    }
+
+    /// The hash of the file content, from the same hasher as the diff, so
+    /// that it's only opened when its destination doesn't match.
+    pub fn hash(mut self, hash: u64) -> Self {
+        self.hash = Some(hash);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+}
+
+impl fmt::Debug for ListedEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ListedEntry")
+            .field("path", &self.path)
+            .field("kind", &self.kind)
+            .field("hash", &self.hash)
+            .finish_non_exhaustive()
+    }
+}
+
+pub fn write_on_listing_diff<'a, H: Hasher + Default>(
+    entries: impl IntoIterator<Item = io::Result<ListedEntry<'a>>>,
+    to: impl AsRef<Path>,
+) -> io::Result<()> {
+    write_on_listing_diff_with_options::<H>(entries, to, &mut DiffOptions::default()).map(|_| ())
+}
+
+/// [`write_on_dir_diff_with_options`](crate::write_on_dir_diff_with_options)
+/// taking the source tree from `entries` rather than from a directory, e.g.
+/// to sync files stored in a database to disk.
+///
+/// Files are read in memory one at a time to be compared, unless their
+/// [`hash`](ListedEntry::hash) is known: their content is then only opened
+/// to be written. Parent directories are created as needed, or reported as
+/// changed with
+/// [`DiffOptions::assert_unchanged`](crate::DiffOptions::assert_unchanged), and with
+/// [`DiffOptions::mirror`](crate::DiffOptions::mirror), destination entries
+/// that aren't listed are deleted from the directories holding listed ones.
+/// Paths that are absolute or have `..` components fail rather than escape
+/// `to`. Errors yielded by `entries` fail the diff.
+pub fn write_on_listing_diff_with_options<'a, H: Hasher + Default>(
+    entries: impl IntoIterator<Item = io::Result<ListedEntry<'a>>>,
+    to: impl AsRef<Path>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<DirDiffStats> {
+    diff_into(to.as_ref(), options, |walk, to| {
+        walk.ensure_dir(to)?;
+        let mut kept = Kept::default();
+        for entry in entries {
+            if walk.stopped() {
+                return Ok(());
+            }
+            let mut entry = entry?;
+            let rel = &entry.path;
+            let to = to.join(rel);
+            let diffed = (|| {
+                ensure_relative(rel)?;
+                let Some(content) = &mut entry.content else {
+                    return walk.ensure_dir(&to);
+                };
+                if let Some(parent) = to.parent() {
+                    walk.ensure_dir(parent)?;
+                }
+                let mut read = || -> io::Result<Vec<u8>> {
+                    let mut bytes = Vec::new();
+                    content()?.read_to_end(&mut bytes)?;
+                    Ok(bytes)
+                };
+                let outcome = match entry.hash {
+                    Some(hash) => write_on_lazy_bytes_diff_with_options::<H>(
+                        &to,
+                        hash,
+                        || read().context(Operation::Read, Side::Source, rel),
+                        walk.options,
+                    )?,
+                    None => {
+                        let bytes = read().context(Operation::Read, Side::Source, rel)?;
+                        write_on_bytes_diff_with_options::<H>(&bytes, &to, walk.options)?
+                    }
+                };
+                walk.record(&to, outcome);
+                Ok(())
+            })();
+            match diffed {
+                Ok(()) if walk.mirror() => match entry.kind {
+                    EntryKind::File => kept.insert(rel),
+                    EntryKind::Dir => kept.insert_dir(rel),
+                },
+                Ok(()) => {}
+                Err(e) => walk.fail(e, rel)?,
+            }
+        }
+        kept.prune(walk, to)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash_reader, SkipReason, WodError, WriteOutcome};
+    use rustc_hash::FxHasher;
+    use std::{cell::Cell, fs};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_listing_diff() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("known.txt"), "known")?;
+        fs::create_dir(to_dir.path().join("empty"))?;
+        fs::write(to_dir.path().join("empty/stale.txt"), "stale")?;
+
+        let opened = Cell::new(0);
+        let open = |content: &'static str| {
+            let opened = &opened;
+            move || {
+                opened.set(opened.get() + 1);
+                Ok(Box::new(content.as_bytes()) as Box<dyn Read>)
+            }
+        };
+        let entries = [
+            ListedEntry::file("known.txt", open("known"))
+                .hash(hash_reader::<FxHasher>(&b"known"[..])?),
+            ListedEntry::file("sub/new.txt", open("new")),
+            ListedEntry::dir("empty"),
+        ];
+
+        let mut outcomes = Vec::new();
+        let mut options = DiffOptions::new()
+            .mirror(true)
+            .on_write(|rel, _, outcome| outcomes.push((rel.to_owned(), outcome)));
+        let stats = write_on_listing_diff_with_options::<FxHasher>(
+            entries.into_iter().map(Ok),
+            to_dir.path(),
+            &mut options,
+        )?;
+        drop(options);
+
+        assert_eq!(
+            outcomes,
+            [
+                (
+                    "known.txt".into(),
+                    WriteOutcome::Skipped(SkipReason::HashMatch)
+                ),
+                ("sub/new.txt".into(), WriteOutcome::Created),
+            ]
+        );
+        assert_eq!(opened.get(), 1);
+        assert_eq!(stats.deleted, [to_dir.path().join("empty/stale.txt")]);
+        assert_eq!(fs::read(to_dir.path().join("sub/new.txt"))?, b"new");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_listing_diff_assert_unchanged() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to = to_dir.path().join("out");
+        let entries = vec![
+            Ok(ListedEntry::dir("empty")),
+            Ok(ListedEntry::file("sub/a.txt", || {
+                Ok(Box::new(&b"a"[..]) as _)
+            })),
+        ];
+
+        let mut options = DiffOptions::new().assert_unchanged(true);
+        let err =
+            write_on_listing_diff_with_options::<FxHasher>(entries, &to, &mut options).unwrap_err();
+
+        let Some(WodError::Changed { paths }) = WodError::from_io(&err) else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(
+            paths,
+            &[
+                to.clone(),
+                to.join("empty"),
+                to.join("sub"),
+                to.join("sub/a.txt"),
+            ]
+        );
+        assert!(!to.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    hash::Hasher,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diff_into, ensure_relative, write_on_bytes_diff_with_options, DiffOptions, DirDiffStats,
+    DirWalk,
+};
+
+pub fn write_on_memdir_diff<H: Hasher + Default>(
+    tree: &BTreeMap<PathBuf, Vec<u8>>,
+    to: impl AsRef<Path>,
+) -> io::Result<()> {
+    write_on_memdir_diff_with_options::<H>(tree, to, &mut DiffOptions::default()).map(|_| ())
+}
+
+/// [`write_on_dir_diff_with_options`](crate::write_on_dir_diff_with_options)
+/// taking the source tree from memory, mapping paths relative to `to` to the
+/// content of each file, e.g. the output of a code generator.
+///
+/// Parent directories are created as needed, or reported as changed with
+/// [`DiffOptions::assert_unchanged`](crate::DiffOptions::assert_unchanged). With
+/// [`DiffOptions::mirror`](crate::DiffOptions::mirror), destination entries
+/// missing from `tree` are deleted from every directory holding one of its
+/// files. Paths that are absolute or have `..` components fail rather than
+/// escape `to`.
+pub fn write_on_memdir_diff_with_options<H: Hasher + Default>(
+    tree: &BTreeMap<PathBuf, Vec<u8>>,
+    to: impl AsRef<Path>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<DirDiffStats> {
+    diff_into(to.as_ref(), options, |walk, to| {
+        walk.ensure_dir(to)?;
+        let mut kept = Kept::default();
+        for (rel, content) in tree {
+            if walk.stopped() {
+                return Ok(());
+            }
+            let to = to.join(rel);
+            let diffed = (|| {
+                ensure_relative(rel)?;
+                if let Some(parent) = to.parent() {
+                    walk.ensure_dir(parent)?;
+                }
+                let outcome = write_on_bytes_diff_with_options::<H>(content, &to, walk.options)?;
+                walk.record(&to, outcome);
+                Ok(())
+            })();
+            match diffed {
+                Ok(()) if walk.mirror() => kept.insert(rel),
+                Ok(()) => {}
+                Err(e) => walk.fail(e, rel)?,
+            }
+        }
+        kept.prune(walk, to)
+    })
+}
+
+/// The names kept in each destination directory when mirroring a tree
+/// listed path by path.
+#[derive(Default)]
+pub(crate) struct Kept(BTreeMap<PathBuf, HashSet<OsString>>);
+
+impl Kept {
+    /// Keep the entry at `rel` and its parents.
+    pub(crate) fn insert(&mut self, rel: &Path) {
+        for ancestor in rel.ancestors() {
+            if let (Some(parent), Some(name)) = (ancestor.parent(), ancestor.file_name()) {
+                let parent = parent.to_path_buf();
+                self.0.entry(parent).or_default().insert(name.to_owned());
+            }
+        }
+    }
+
+    /// Keep the directory at `rel`, with only the entries inserted below it.
+    pub(crate) fn insert_dir(&mut self, rel: &Path) {
+        self.insert(rel);
+        self.0.entry(rel.to_path_buf()).or_default();
+    }
+
+    /// Delete the entries of `to` that weren't kept from the directories
+    /// holding kept ones.
+    pub(crate) fn prune(self, walk: &mut DirWalk<'_, '_, '_>, to: &Path) -> io::Result<()> {
+        for (dir, kept) in self.0 {
+            let dir = to.join(dir);
+            if let Err(e) = walk.prune(&dir, &kept) {
+                walk.fail(e, &dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SkipReason, WodError, WriteOutcome};
+    use rustc_hash::FxHasher;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_memdir_diff_mirror() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        fs::create_dir_all(to_dir.path().join("gen/old"))?;
+        fs::write(to_dir.path().join("gen/same.rs"), "same")?;
+        fs::write(to_dir.path().join("gen/stale.rs"), "stale")?;
+        fs::write(to_dir.path().join("gen/old/x.rs"), "x")?;
+        let tree = BTreeMap::from([
+            (PathBuf::from("gen/same.rs"), b"same".to_vec()),
+            (PathBuf::from("gen/new/mod.rs"), b"new".to_vec()),
+        ]);
+
+        let mut outcomes = Vec::new();
+        let mut options = DiffOptions::new()
+            .mirror(true)
+            .on_write(|rel, _, outcome| outcomes.push((rel.to_owned(), outcome)));
+        let stats =
+            write_on_memdir_diff_with_options::<FxHasher>(&tree, to_dir.path(), &mut options)?;
+        drop(options);
+
+        assert_eq!(
+            outcomes,
+            [
+                ("gen/new/mod.rs".into(), WriteOutcome::Created),
+                (
+                    "gen/same.rs".into(),
+                    WriteOutcome::Skipped(SkipReason::SizeAndHashMatch)
+                ),
+            ]
+        );
+        let mut deleted = stats.deleted;
+        deleted.sort();
+        assert_eq!(
+            deleted,
+            [
+                to_dir.path().join("gen/old"),
+                to_dir.path().join("gen/stale.rs")
+            ]
+        );
+        assert_eq!(fs::read(to_dir.path().join("gen/new/mod.rs"))?, b"new");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memdir_diff_assert_unchanged() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to = to_dir.path().join("out");
+        let tree = BTreeMap::from([
+            (PathBuf::from("gen/a.rs"), b"a".to_vec()),
+            (PathBuf::from("gen/b.rs"), b"b".to_vec()),
+        ]);
+
+        let mut options = DiffOptions::new().assert_unchanged(true);
+        let err =
+            write_on_memdir_diff_with_options::<FxHasher>(&tree, &to, &mut options).unwrap_err();
+
+        let Some(WodError::Changed { paths }) = WodError::from_io(&err) else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(
+            paths,
+            &[
+                to.clone(),
+                to.join("gen"),
+                to.join("gen/a.rs"),
+                to.join("gen/b.rs"),
+            ]
+        );
+        assert!(!to.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::Context, Operation, Side};
+
+/// A node of the BLAKE3 Merkle tree of a directory, see [`merkle_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MerkleNode {
+    /// A file, hashed by content.
+    File { hash: [u8; 32] },
+    /// A directory, hashed from the names, kinds and hashes of its entries.
+    Dir {
+        hash: [u8; 32],
+        children: BTreeMap<String, MerkleNode>,
+    },
+}
+
+impl MerkleNode {
+    pub fn hash(&self) -> [u8; 32] {
+        match self {
+            MerkleNode::File { hash } | MerkleNode::Dir { hash, .. } => *hash,
+        }
+    }
+
+    /// The topmost paths, relative to the root, where `self` and `other`
+    /// differ: an entry present on one side only, or a file whose content
+    /// changed. Subtrees with equal hashes are not visited.
+    pub fn diff(&self, other: &MerkleNode) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        diff(self, other, PathBuf::new(), &mut changed);
+        changed
+    }
+}
+
+fn diff(a: &MerkleNode, b: &MerkleNode, path: PathBuf, changed: &mut Vec<PathBuf>) {
+    if a.hash() == b.hash() {
+        return;
+    }
+    let (
+        MerkleNode::Dir {
+            children: a_children,
+            ..
+        },
+        MerkleNode::Dir {
+            children: b_children,
+            ..
+        },
+    ) = (a, b)
+    else {
+        changed.push(path);
+        return;
+    };
+    for (name, a_child) in a_children {
+        match b_children.get(name) {
+            Some(b_child) => diff(a_child, b_child, path.join(name), changed),
+            None => changed.push(path.join(name)),
+        }
+    }
+    for name in b_children.keys() {
+        if !a_children.contains_key(name) {
+            changed.push(path.join(name));
+        }
+    }
+}
+
+/// The BLAKE3 Merkle tree of the directory `path`, where each directory node
+/// hashes its entries, so that comparing two trees with
+/// [`MerkleNode::diff`] only descends into the subtrees that changed.
+pub fn merkle_tree(path: impl AsRef<Path>) -> io::Result<MerkleNode> {
+    let dir = path.as_ref();
+    let mut children = BTreeMap::new();
+    // Listed in full first, not to hold a handle per level of the tree.
+    let entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .context(Operation::ReadDir, Side::Source, dir)?;
+    for entry in entries {
+        let path = entry.path();
+        let node = if path.is_dir() {
+            merkle_tree(&path)?
+        } else {
+            let file = File::open(&path).context(Operation::Read, Side::Source, &path)?;
+            let mut hasher = blake3::Hasher::new();
+            hasher
+                .update_reader(file)
+                .context(Operation::Read, Side::Source, &path)?;
+            MerkleNode::File {
+                hash: *hasher.finalize().as_bytes(),
+            }
+        };
+        children.insert(entry.file_name().to_string_lossy().into_owned(), node);
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    for (name, child) in &children {
+        hasher.update(name.as_bytes());
+        hasher.update(match child {
+            MerkleNode::File { .. } => b"\0f",
+            MerkleNode::Dir { .. } => b"\0d",
+        });
+        hasher.update(&child.hash());
+    }
+    Ok(MerkleNode::Dir {
+        hash: *hasher.finalize().as_bytes(),
+        children,
+    })
+}
+
+/// The root hash of [`merkle_tree`], to store and compare later.
+pub fn merkle_root(path: impl AsRef<Path>) -> io::Result<[u8; 32]> {
+    Ok(merkle_tree(path)?.hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_merkle_tree_diff() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("a/b"))?;
+        fs::create_dir_all(dir.path().join("c"))?;
+        fs::write(dir.path().join("a/b/f.txt"), "f")?;
+        fs::write(dir.path().join("c/g.txt"), "g")?;
+        let before = merkle_tree(dir.path())?;
+        assert_eq!(merkle_root(dir.path())?, before.hash());
+
+        fs::write(dir.path().join("a/b/f.txt"), "changed")?;
+        fs::write(dir.path().join("c/new.txt"), "new")?;
+        let after = merkle_tree(dir.path())?;
+
+        assert_ne!(before.hash(), after.hash());
+        assert_eq!(
+            before.diff(&after),
+            [PathBuf::from("a/b/f.txt"), PathBuf::from("c/new.txt")]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,864 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::OsString,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+    time::SystemTime,
+};
+
+use crate::{
+    comparator::ComparatorRegistry, compress::Codec, copy::OnProgress, sidecar::SidecarPath,
+    similarity::OnSimilarity, CaseCollisions, Conflict, ConflictAction, DirDiffStats, FileLimit,
+    FileSystem, RetryPolicy, TextOptions, WriteOutcome,
+};
+
+type MapPath<'a> = Box<dyn FnMut(&Path) -> PathBuf + 'a>;
+type Baseline<'a> = Box<dyn FnMut(&Path) -> Option<u64> + 'a>;
+type OnConflict<'a> = Box<dyn FnMut(&Conflict<'_>) -> ConflictAction + 'a>;
+type OnWrite<'a> = Box<dyn FnMut(&Path, &Path, WriteOutcome) + 'a>;
+type Protect<'a> = Box<dyn FnMut(&Path) -> bool + 'a>;
+type OnFinish<'a> = Box<dyn FnMut(&DirDiffStats) + 'a>;
+type OnHash<'a> = Box<dyn FnMut(&Path, u64) + 'a>;
+type OnHashCollision<'a> = Box<dyn FnMut(&Path) + 'a>;
+type Crypt<'a> = Box<dyn FnMut(&Path, &[u8]) -> io::Result<Vec<u8>> + 'a>;
+pub(crate) type Canonicalize<'a> = Box<dyn for<'b> FnMut(&'b [u8]) -> Cow<'b, [u8]> + 'a>;
+pub(crate) type Compare<'a> = Box<dyn FnMut(&[u8], &[u8]) -> bool + 'a>;
+
+/// What to do after source and destination hashes matched.
+///
+/// Since hashes are only 64 bits wide, a match may rarely hide different
+/// content. Verifying costs a full read of both sides, so a common setup is
+/// to only enable it when `cfg!(debug_assertions)` is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyOnMatch {
+    /// Trust the hashes.
+    #[default]
+    Off,
+    /// Compare the bytes and write if they differ, reporting the collision
+    /// to [`DiffOptions::on_hash_collision`] if set.
+    Warn,
+    /// Compare the bytes and fail with [`WodError::HashCollision`](crate::WodError::HashCollision) if they differ.
+    Error,
+}
+
+/// When a destination gets written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Write when the content differs.
+    #[default]
+    Diff,
+    /// Only write missing destinations, e.g. to seed default config files
+    /// without clobbering user edits. Existing ones are reported as
+    /// [`SkipReason::DestExists`](crate::SkipReason::DestExists) whatever
+    /// their content, without reading either side.
+    CreateIfAbsent,
+}
+
+/// The order a directory diff handles the entries of each directory in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntryOrder {
+    /// The order the filesystem lists them in, which is unspecified.
+    #[default]
+    Native,
+    /// By name, for reproducible runs.
+    Name,
+    /// By source modification time, newest first, so that recent changes
+    /// land first if a long run gets cut short. Ties are ordered by name.
+    NewestFirst,
+}
+
+/// What to do when a destination about to be written is a symbolic link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DestSymlinks {
+    /// Remove the link and write a regular file in its place, so a sync never
+    /// writes outside the destination tree.
+    #[default]
+    Replace,
+    /// Write to the file the link points to.
+    Follow,
+}
+
+/// What a directory diff does with a source symlink pointing to nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BrokenSymlinks {
+    /// Leave it out, reported as
+    /// [`SkipReason::BrokenSymlink`](crate::SkipReason::BrokenSymlink).
+    #[default]
+    Skip,
+    /// Create the same dangling symlink in the destination, replacing
+    /// whatever is there unless it's already that link.
+    Recreate,
+    /// Fail on it like on any other entry that can't be read.
+    Error,
+}
+
+/// What to do when an existing destination can't be read to compare it,
+/// e.g. for lack of permissions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DestUnreadable {
+    /// Write the source over it, as for a destination with different
+    /// content, reported as
+    /// [`WriteOutcome::Overwritten`](crate::WriteOutcome::Overwritten).
+    #[default]
+    Overwrite,
+    /// Fail with [`WodError::DestinationUnreadable`](crate::WodError::DestinationUnreadable).
+    Error,
+}
+
+/// How much of each end of a file [`DiffOptions::sample`] compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleSpec {
+    /// Bytes compared at the start.
+    pub head: u64,
+    /// Bytes compared at the end.
+    pub tail: u64,
+}
+
+/// How files with a given extension are compared, see
+/// [`DiffOptions::strategy`].
+#[derive(Debug, Clone)]
+pub enum CompareStrategy {
+    /// Compare the raw bytes through their hash.
+    Raw,
+    /// Compare as text, normalized according to the options.
+    Text(TextOptions),
+}
+
+/// What to do when a source file is modified while being copied, leaving a
+/// destination mixing old and new content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceChange {
+    /// Don't check.
+    #[default]
+    Ignore,
+    /// Copy again up to `attempts` more times, then fail like
+    /// [`SourceChange::Error`].
+    Retry { attempts: u32 },
+    /// Fail with
+    /// [`WodError::SourceChangedDuringCopy`](crate::WodError::SourceChangedDuringCopy).
+    Error,
+}
+
+/// Where [`DiffOptions::backup`] keeps the previous content of overwritten
+/// destinations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backup {
+    /// Next to the destination, its name followed by the suffix, e.g.
+    /// `file.ext.bak` for `".bak"`.
+    Suffix(OsString),
+    /// Below this directory, at the destination path relative to the
+    /// directory diff root, or at the destination name for single files.
+    Dir(PathBuf),
+}
+
+/// Knobs shared by the `*_with_options` functions.
+///
+/// The defaults behave exactly like the plain `write_on_*_diff` functions.
+#[derive(Default)]
+pub struct DiffOptions<'a> {
+    pub(crate) retry: RetryPolicy,
+    pub(crate) text: Option<TextOptions>,
+    pub(crate) verify_on_match: VerifyOnMatch,
+    pub(crate) on_hash_collision: Option<OnHashCollision<'a>>,
+    pub(crate) map_path: Option<MapPath<'a>>,
+    pub(crate) skip_dest_modified_after: Option<SystemTime>,
+    pub(crate) baseline: Option<Baseline<'a>>,
+    pub(crate) on_conflict: Option<OnConflict<'a>>,
+    pub(crate) continue_on_error: bool,
+    pub(crate) case_collisions: CaseCollisions,
+    pub(crate) on_progress: Option<OnProgress<'a>>,
+    pub(crate) dest_symlinks: DestSymlinks,
+    pub(crate) broken_symlinks: BrokenSymlinks,
+    pub(crate) dest_unreadable: DestUnreadable,
+    pub(crate) write_mode: WriteMode,
+    pub(crate) journal: Option<(PathBuf, String)>,
+    pub(crate) compressed_dest: bool,
+    pub(crate) crypt: Option<(Crypt<'a>, Crypt<'a>)>,
+    pub(crate) canonicalize: Option<Canonicalize<'a>>,
+    pub(crate) compare: Option<Compare<'a>>,
+    pub(crate) comparators: Option<ComparatorRegistry<'a>>,
+    pub(crate) dest_prefix: Option<PathBuf>,
+    pub(crate) strip_prefix: Option<PathBuf>,
+    pub(crate) mirror: bool,
+    pub(crate) protect: Option<Protect<'a>>,
+    pub(crate) treat_empty_dest_as_present: bool,
+    pub(crate) sparse: bool,
+    pub(crate) check_free_space: bool,
+    pub(crate) preserve_file_flags: bool,
+    pub(crate) entry_order: EntryOrder,
+    pub(crate) backup: Option<Backup>,
+    pub(crate) dedup: bool,
+    pub(crate) on_write: Option<OnWrite<'a>>,
+    pub(crate) relative_paths: bool,
+    pub(crate) collect_files: bool,
+    pub(crate) assert_unchanged: bool,
+    pub(crate) on_source_change: SourceChange,
+    pub(crate) cancel: Option<&'a AtomicBool>,
+    pub(crate) max_bytes_written: Option<u64>,
+    pub(crate) min_size: Option<u64>,
+    pub(crate) max_size: Option<u64>,
+    pub(crate) sidecar: Option<SidecarPath<'a>>,
+    pub(crate) strategies: HashMap<OsString, CompareStrategy>,
+    pub(crate) touch_on_skip: bool,
+    pub(crate) two_tier: bool,
+    pub(crate) sample: Option<SampleSpec>,
+    pub(crate) preserve_inode: bool,
+    pub(crate) on_similarity: Option<OnSimilarity<'a>>,
+    pub(crate) create_mode: Option<u32>,
+    pub(crate) skip_locked: bool,
+    pub(crate) on_finish: Option<OnFinish<'a>>,
+    pub(crate) on_hash: Option<OnHash<'a>>,
+    pub(crate) file_limit: Option<FileLimit>,
+    pub(crate) file_system: Option<&'a dyn FileSystem>,
+    /// The destination root of the running directory diff, to place backups.
+    pub(crate) dest_root: Option<PathBuf>,
+    /// Hash collisions found so far, for
+    /// [`DirDiffStats::hash_collisions`](crate::DirDiffStats::hash_collisions).
+    pub(crate) hash_collisions: usize,
+    #[cfg(feature = "blake3")]
+    pub(crate) b3sums: bool,
+    /// The BLAKE3 digest of the source of the file being diffed, once read.
+    #[cfg(feature = "blake3")]
+    pub(crate) b3sum: Option<[u8; 32]>,
+    #[cfg(feature = "ignore")]
+    pub(crate) ignore_files: bool,
+}
+
+impl<'a> DiffOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retry transient failures of reads and copies.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Compare content as text, normalized according to `text`.
+    pub fn text(mut self, text: TextOptions) -> Self {
+        self.text = Some(text);
+        self
+    }
+
+    /// Double check matching hashes by comparing the actual bytes.
+    pub fn verify_on_match(mut self, verify_on_match: VerifyOnMatch) -> Self {
+        self.verify_on_match = verify_on_match;
+        self
+    }
+
+    /// Call `on_hash_collision` with each destination whose hash matched the
+    /// source while the bytes differed under [`VerifyOnMatch::Warn`], before
+    /// it's rewritten, e.g. to log it.
+    pub fn on_hash_collision(mut self, on_hash_collision: impl FnMut(&Path) + 'a) -> Self {
+        self.on_hash_collision = Some(Box::new(on_hash_collision));
+        self
+    }
+
+    /// Compute each destination path of a directory diff from the source
+    /// path relative to the source root, for example to flatten a tree.
+    ///
+    /// The returned path is joined to the destination root and missing parent
+    /// directories are created. Source directories are not mirrored as such,
+    /// only the directories holding mapped files exist in the destination.
+    pub fn map_path(mut self, map_path: impl FnMut(&Path) -> PathBuf + 'a) -> Self {
+        self.map_path = Some(Box::new(map_path));
+        self
+    }
+
+    /// Never write over a destination modified after `cutoff`, to protect
+    /// manual edits from an automated sync. Such files are reported as
+    /// [`SkipReason::DestNewer`](crate::SkipReason::DestNewer).
+    pub fn skip_dest_modified_after(mut self, cutoff: SystemTime) -> Self {
+        self.skip_dest_modified_after = Some(cutoff);
+        self
+    }
+
+    /// Look up the hash a destination had after the last sync, to detect
+    /// when both sides changed since. Used together with
+    /// [`DiffOptions::on_conflict`].
+    pub fn baseline(mut self, baseline: impl FnMut(&Path) -> Option<u64> + 'a) -> Self {
+        self.baseline = Some(Box::new(baseline));
+        self
+    }
+
+    /// Decide what to do when a destination about to be written changed
+    /// since its baseline just like the source did. Without a callback, or
+    /// without a baseline for the destination, the source is written.
+    pub fn on_conflict(
+        mut self,
+        on_conflict: impl FnMut(&Conflict<'_>) -> ConflictAction + 'a,
+    ) -> Self {
+        self.on_conflict = Some(Box::new(on_conflict));
+        self
+    }
+
+    /// Keep going when an entry of a directory diff fails, collecting the
+    /// error in [`DirDiffStats::errors`](crate::DirDiffStats::errors).
+    pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+
+    /// Detect names differing only by case within a directory.
+    pub fn case_collisions(mut self, case_collisions: CaseCollisions) -> Self {
+        self.case_collisions = case_collisions;
+        self
+    }
+
+    /// Report the progress of each write as `(destination, bytes copied, total
+    /// bytes)`, called after every chunk.
+    ///
+    /// Writes then go through a chunked copy loop instead of [`std::fs::copy`],
+    /// so leave it unset when progress isn't needed.
+    pub fn on_progress(mut self, on_progress: impl FnMut(&Path, u64, u64) + 'a) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Choose whether writes replace destination symlinks or go through them.
+    pub fn dest_symlinks(mut self, dest_symlinks: DestSymlinks) -> Self {
+        self.dest_symlinks = dest_symlinks;
+        self
+    }
+
+    /// Choose whether an existing destination that can't be read is
+    /// overwritten, the default, or fails the diff. Destinations told apart
+    /// by their size aren't read, so they're overwritten either way.
+    pub fn dest_unreadable(mut self, dest_unreadable: DestUnreadable) -> Self {
+        self.dest_unreadable = dest_unreadable;
+        self
+    }
+
+    /// Choose what a directory diff does with source symlinks whose target
+    /// doesn't exist, skipped by default.
+    pub fn broken_symlinks(mut self, broken_symlinks: BrokenSymlinks) -> Self {
+        self.broken_symlinks = broken_symlinks;
+        self
+    }
+
+    /// Choose when destinations get written.
+    pub fn write_mode(mut self, write_mode: WriteMode) -> Self {
+        self.write_mode = write_mode;
+        self
+    }
+
+    /// Record the destinations a directory diff completed in a journal file
+    /// at `path`, so that a run interrupted midway can be resumed: running
+    /// again with the same `session` skips them without reading them, as
+    /// [`SkipReason::Resumed`](crate::SkipReason::Resumed).
+    ///
+    /// A missing journal or one from another session starts a full run. The
+    /// journal is removed once a run completes without errors.
+    pub fn journal(mut self, path: impl Into<PathBuf>, session: impl Into<String>) -> Self {
+        self.journal = Some((path.into(), session.into()));
+        self
+    }
+
+    /// Treat destinations ending in `.gz` (`flate2` feature) or `.zst`
+    /// (`zstd` feature) as compressed: the source is compared against their
+    /// decompressed content and written compressed. A destination that fails
+    /// to decompress is rewritten.
+    #[cfg(any(feature = "flate2", feature = "zstd"))]
+    pub fn compressed_dest(mut self, compressed_dest: bool) -> Self {
+        self.compressed_dest = compressed_dest;
+        self
+    }
+
+    /// Treat destinations as encrypted at rest: the source is compared
+    /// against `decrypt(to, content)` and written as `encrypt(to, content)`,
+    /// so re-encrypting with a new IV or key doesn't cause rewrites. Both
+    /// get the destination path, e.g. to look up a per-file key.
+    ///
+    /// Together with [`compressed_dest`](Self::compressed_dest), content is
+    /// compressed before being encrypted. A destination that fails to
+    /// decrypt is rewritten.
+    pub fn encrypted_dest(
+        mut self,
+        decrypt: impl FnMut(&Path, &[u8]) -> io::Result<Vec<u8>> + 'a,
+        encrypt: impl FnMut(&Path, &[u8]) -> io::Result<Vec<u8>> + 'a,
+    ) -> Self {
+        self.crypt = Some((Box::new(decrypt), Box::new(encrypt)));
+        self
+    }
+
+    /// Compare both sides as `canonicalize` rewrites them, e.g. with an
+    /// embedded build date blanked out, so files differing only there
+    /// aren't rewritten. The source is still written as is.
+    ///
+    /// Applied after [`text`](Self::text) normalization, holding both sides
+    /// in memory.
+    pub fn canonicalize(
+        mut self,
+        canonicalize: impl for<'b> FnMut(&'b [u8]) -> Cow<'b, [u8]> + 'a,
+    ) -> Self {
+        self.canonicalize = Some(Box::new(canonicalize));
+        self
+    }
+
+    /// Decide whether the existing destination is equal to the source with
+    /// `compare(from, to)` instead of comparing hashes, for domain specific
+    /// equality such as JSON documents parsing to the same value.
+    ///
+    /// Both sides are read in memory in full to be handed to the comparator,
+    /// after the normalization of [`DiffOptions::text`] if any. Destinations
+    /// found equal are reported as
+    /// [`SkipReason::Equivalent`](crate::SkipReason::Equivalent).
+    pub fn compare(mut self, compare: impl FnMut(&[u8], &[u8]) -> bool + 'a) -> Self {
+        self.compare = Some(Box::new(compare));
+        self
+    }
+
+    /// Compare each file with the comparator `comparators` picks from the
+    /// source content, falling back to hashes for unrecognized files. Like
+    /// with [`compare`](Self::compare), which takes precedence, both sides
+    /// are read in full and equal destinations are reported as
+    /// [`SkipReason::Equivalent`](crate::SkipReason::Equivalent).
+    pub fn comparators(mut self, comparators: ComparatorRegistry<'a>) -> Self {
+        self.comparators = Some(comparators);
+        self
+    }
+
+    /// Sync a directory into `prefix` below the destination root, created as
+    /// needed. Reported paths include the prefix, and deletions of
+    /// [`DiffOptions::mirror`] stay below it.
+    pub fn dest_prefix(mut self, prefix: impl Into<PathBuf>) -> Self {
+        self.dest_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only sync what's below `prefix` in the source, with the prefix
+    /// stripped from destination paths: `build/dist/app.js` lands at
+    /// `app.js` with a prefix of `build/dist`. Changed paths given to
+    /// [`write_on_paths_diff`](crate::write_on_paths_diff) outside of it are
+    /// ignored. Combines with [`DiffOptions::dest_prefix`].
+    pub fn strip_prefix(mut self, prefix: impl Into<PathBuf>) -> Self {
+        self.strip_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Delete destination entries without a source counterpart, making a
+    /// directory diff an exact mirror. Deleted paths are listed in
+    /// [`DirDiffStats::deleted`](crate::DirDiffStats::deleted).
+    ///
+    /// Entries skipped on the source side, such as ignored ones, keep their
+    /// destination. Has no effect together with [`DiffOptions::map_path`].
+    pub fn mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Keep the destination entries for which `protect` returns true from
+    /// the deletions of [`DiffOptions::mirror`], such as files generated at
+    /// runtime. It's given their path relative to the destination root, and
+    /// a protected directory keeps everything below it.
+    ///
+    /// For example `|path| path.file_name().is_some_and(|name|
+    /// name.as_encoded_bytes().starts_with(b"."))` protects dotfiles.
+    pub fn protect(mut self, protect: impl FnMut(&Path) -> bool + 'a) -> Self {
+        self.protect = Some(Box::new(protect));
+        self
+    }
+
+    /// Keep existing zero-byte destinations, such as deliberate placeholders,
+    /// instead of filling them with the source content. They are reported as
+    /// [`SkipReason::EmptyDest`](crate::SkipReason::EmptyDest), or as
+    /// [`SkipReason::BothEmpty`](crate::SkipReason::BothEmpty) when the source
+    /// is empty too.
+    pub fn treat_empty_dest_as_present(mut self, treat_empty_dest_as_present: bool) -> Self {
+        self.treat_empty_dest_as_present = treat_empty_dest_as_present;
+        self
+    }
+
+    /// Keep the holes of sparse source files, such as VM images, when copying
+    /// them instead of writing out their zeros.
+    ///
+    /// Holes are only detected on Linux, through `SEEK_DATA`/`SEEK_HOLE`, and
+    /// on filesystems tracking them (ext4, XFS, Btrfs, tmpfs, ...). Elsewhere
+    /// files are copied as usual. Sparse copies don't report
+    /// [`DiffOptions::on_progress`].
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Check the destination filesystem has room for each write before
+    /// starting it, failing with
+    /// [`WodError::InsufficientSpace`](crate::WodError::InsufficientSpace)
+    /// rather than leaving a truncated file behind. The space taken by the
+    /// overwritten destination counts as available.
+    ///
+    /// Only supported on Unix, through `statvfs`. Concurrent writers can
+    /// still fill the disk between the check and the write.
+    pub fn check_free_space(mut self, check_free_space: bool) -> Self {
+        self.check_free_space = check_free_space;
+        self
+    }
+
+    /// Copy the file flags of source files (`st_flags`, e.g. `UF_IMMUTABLE`
+    /// or `UF_HIDDEN`) to the destinations written from them.
+    ///
+    /// Only supported on macOS and the BSDs, a no-op elsewhere. Note that a
+    /// destination made immutable can't be written by a later run.
+    pub fn preserve_file_flags(mut self, preserve_file_flags: bool) -> Self {
+        self.preserve_file_flags = preserve_file_flags;
+        self
+    }
+
+    /// Choose the order entries are handled in within each directory of a
+    /// directory diff, also the order of progress and outcome reports.
+    /// Subdirectories are walked where they come in that order.
+    pub fn entry_order(mut self, entry_order: EntryOrder) -> Self {
+        self.entry_order = entry_order;
+        self
+    }
+
+    /// Keep the previous version of each destination about to be
+    /// overwritten, moved aside before the write. Created and skipped
+    /// destinations aren't backed up, and an existing backup is replaced.
+    pub fn backup(mut self, backup: Backup) -> Self {
+        self.backup = Some(backup);
+        self
+    }
+
+    /// Hardlink the destinations a directory diff creates to an earlier
+    /// destination of the same run holding identical content, instead of
+    /// storing another copy. Falls back to copying when linking fails, e.g.
+    /// across devices. Existing destinations are diffed as usual.
+    ///
+    /// Every source file gets hashed to find duplicates, and with
+    /// [`DiffOptions::verify_on_match`] their bytes are compared before
+    /// linking. Destinations shared by several links are replaced rather
+    /// than written through when they change later. Has no effect together
+    /// with [`DiffOptions::text`], [`DiffOptions::compare`] or compressed or
+    /// encrypted destinations, whose content differs from the source.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Report the outcome of each file of a directory diff as `(path relative
+    /// to the destination root, destination path, outcome)`, skips included.
+    pub fn on_write(mut self, on_write: impl FnMut(&Path, &Path, WriteOutcome) + 'a) -> Self {
+        self.on_write = Some(Box::new(on_write));
+        self
+    }
+
+    /// List paths in [`DirDiffStats`](crate::DirDiffStats) relative to the
+    /// destination root, `sub/c.txt` rather than the full destination path.
+    /// Error paths stay as they are, since they may be source paths.
+    pub fn relative_paths(mut self, relative_paths: bool) -> Self {
+        self.relative_paths = relative_paths;
+        self
+    }
+
+    /// List every destination file the diff wrote or found up to date in
+    /// [`DirDiffStats::files`](crate::DirDiffStats::files), e.g. to run a
+    /// post-step on each of them without walking the destination again.
+    pub fn collect_files(mut self, collect_files: bool) -> Self {
+        self.collect_files = collect_files;
+        self
+    }
+
+    /// List the BLAKE3 digest of every source file a directory diff wrote or
+    /// found up to date in
+    /// [`DirDiffStats::b3sums`](crate::DirDiffStats::b3sums), for
+    /// [`write_b3sums`](crate::write_b3sums).
+    ///
+    /// Digests are taken along the reads the diff does anyway, sources being
+    /// read on their own only for files it skipped without reading, e.g.
+    /// resumed ones.
+    #[cfg(feature = "blake3")]
+    pub fn b3sums(mut self, b3sums: bool) -> Self {
+        self.b3sums = b3sums;
+        self
+    }
+
+    /// Compare everything but never write, failing with
+    /// [`WodError::Changed`](crate::WodError::Changed) listing every
+    /// destination that would have been created, overwritten or deleted, like
+    /// `cargo fmt --check` does. Meant to check generated files are up to
+    /// date in CI.
+    ///
+    /// Destinations left alone for another reason, such as
+    /// [`DiffOptions::skip_dest_modified_after`], don't count as changes.
+    pub fn assert_unchanged(mut self, assert_unchanged: bool) -> Self {
+        self.assert_unchanged = assert_unchanged;
+        self
+    }
+
+    /// Check the size and modification time of each source file before and
+    /// after copying it, to catch files written to during the copy such as
+    /// live logs. Sources changing between hashing and copying are fine, the
+    /// copy then holds the newer content.
+    pub fn on_source_change(mut self, on_source_change: SourceChange) -> Self {
+        self.on_source_change = on_source_change;
+        self
+    }
+
+    /// Stop a directory diff once `cancel` is set, e.g. from a UI thread,
+    /// returning the stats so far with
+    /// [`DirDiffStats::cancelled`](crate::DirDiffStats::cancelled) set.
+    ///
+    /// The flag is checked before each entry, so a copy in progress still
+    /// completes and no destination is left half written. Mirror deletions
+    /// of the directories left unfinished don't happen, and a journal is
+    /// kept for the next run to resume from.
+    pub fn cancel(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Stop a directory diff once the destinations written add up to `max`
+    /// bytes or more, returning the stats so far with
+    /// [`DirDiffStats::quota_reached`](crate::DirDiffStats::quota_reached)
+    /// set. Like [`cancel`](Self::cancel), it's checked before each entry and
+    /// a journal is kept, so that the next run goes on where this one
+    /// stopped.
+    pub fn max_bytes_written(mut self, max: u64) -> Self {
+        self.max_bytes_written = Some(max);
+        self
+    }
+
+    /// Only diff source files of at least `min` bytes in a directory diff,
+    /// skipping the others as [`SkipReason::Size`](crate::SkipReason::Size)
+    /// before anything is hashed. Their destinations are kept when
+    /// mirroring.
+    pub fn min_size(mut self, min: u64) -> Self {
+        self.min_size = Some(min);
+        self
+    }
+
+    /// Only diff source files of at most `max` bytes in a directory diff,
+    /// like [`min_size`](Self::min_size), e.g. to sync config files apart
+    /// from large media.
+    pub fn max_size(mut self, max: u64) -> Self {
+        self.max_size = Some(max);
+        self
+    }
+
+    /// Skip source entries excluded by `.gitignore` and `.ignore` files found
+    /// in the source tree, nested files refining the rules of their parents.
+    #[cfg(feature = "ignore")]
+    pub fn ignore_files(mut self, ignore_files: bool) -> Self {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    /// Take the hash of each destination from the sidecar file at
+    /// `sidecar(to)`, e.g. `to` with a `.sha` extension, instead of reading
+    /// the destination, and record it there after each write. Unchanged
+    /// destinations then only cost metadata lookups.
+    ///
+    /// A sidecar stays valid while the size and modification time of its
+    /// destination match the ones recorded with the hash, otherwise the
+    /// destination is hashed and the sidecar refreshed. It must use the same
+    /// hasher across runs. Diffs handled in memory, such as with
+    /// [`DiffOptions::text`], don't use sidecars, and mirroring deletes them
+    /// unless they're kept by [`DiffOptions::protect`].
+    pub fn sidecar(mut self, sidecar: impl FnMut(&Path) -> PathBuf + 'a) -> Self {
+        self.sidecar = Some(Box::new(sidecar));
+        self
+    }
+
+    /// Compare destinations ending in `.{extension}` with `strategy`
+    /// instead of the comparison set for all files, e.g. text for `rs` and
+    /// `txt` files while everything else is compared raw.
+    pub fn strategy(mut self, extension: impl Into<OsString>, strategy: CompareStrategy) -> Self {
+        self.strategies.insert(extension.into(), strategy);
+        self
+    }
+
+    /// Set the modification time of destinations skipped for already holding
+    /// the source content to now, without rewriting them, for make-style
+    /// tools telling what was checked by timestamps.
+    pub fn touch_on_skip(mut self, touch_on_skip: bool) -> Self {
+        self.touch_on_skip = touch_on_skip;
+        self
+    }
+
+    /// Compare a cheap fingerprint of both sides before hashing them, and
+    /// write right away when it differs. Only matching fingerprints get
+    /// confirmed by a full hash.
+    ///
+    /// The fingerprint is the size, the modification time for file sources,
+    /// and the first and last 4 KiB of content. Destinations written from a
+    /// file get its modification time, so that they match on the next run.
+    pub fn two_tier(mut self, two_tier: bool) -> Self {
+        self.two_tier = two_tier;
+        self
+    }
+
+    /// Treat files of the same size whose first and last bytes match, as
+    /// far as `sample` says, as unchanged without hashing them, reported as
+    /// [`SkipReason::Sampled`](crate::SkipReason::Sampled). Files differing
+    /// there are written without hashing either.
+    ///
+    /// This is lossy: a change in the middle of a file is missed. It's meant
+    /// for huge files that are only appended to or replaced, such as logs,
+    /// where reading them in full is too slow.
+    pub fn sample(mut self, sample: SampleSpec) -> Self {
+        self.sample = Some(sample);
+        self
+    }
+
+    /// Keep the inode of overwritten destinations, so that the handles other
+    /// processes hold on them stay valid, where a new file would otherwise
+    /// take their place: [`backup`](Self::backup) copies the previous version
+    /// aside instead of moving it, and a [`DiffSink`](crate::DiffSink)
+    /// rewrites the destination instead of renaming its temporary file over
+    /// it. Diffs already truncate and rewrite destinations in place
+    /// otherwise.
+    ///
+    /// A sink's write isn't atomic then: readers can see the destination
+    /// truncated or partly written, and an interrupted write leaves it so.
+    /// Replaced symlinks and hardlinks split by [`dedup`](Self::dedup) still
+    /// get a new file.
+    pub fn preserve_inode(mut self, preserve_inode: bool) -> Self {
+        self.preserve_inode = preserve_inode;
+        self
+    }
+
+    /// Call `on_similarity` with each destination about to be overwritten
+    /// and how similar it is to its source in percent, e.g. to tell minor
+    /// changes from rewrites in a log. The write happens either way.
+    ///
+    /// The similarity is the share of the source made of content-defined
+    /// chunks of about 1 KiB, cut by a rolling hash, also found in the
+    /// destination. Both sides are read in full for it.
+    pub fn on_similarity(mut self, on_similarity: impl FnMut(&Path, u8) + 'a) -> Self {
+        self.on_similarity = Some(Box::new(on_similarity));
+        self
+    }
+
+    /// Create missing destinations with the permission bits `mode`, e.g.
+    /// `0o600` for secrets, rather than the mode of the source or the one
+    /// the umask leaves. The mode is set when the file is created, before
+    /// any content is written. Overwritten destinations aren't affected.
+    #[cfg(unix)]
+    pub fn create_mode(mut self, mode: u32) -> Self {
+        self.create_mode = Some(mode);
+        self
+    }
+
+    /// Skip files of a directory diff that another process holds open
+    /// rather than failing, reported as
+    /// [`SkipReason::Locked`](crate::SkipReason::Locked). This only happens
+    /// on Windows, see [`RetryPolicy::is_locked`]. The [`retry`](Self::retry)
+    /// policy is tried first, which waits for momentary locks.
+    pub fn skip_locked(mut self, skip_locked: bool) -> Self {
+        self.skip_locked = skip_locked;
+        self
+    }
+
+    /// Call `on_finish` once a directory diff completes, with its stats,
+    /// e.g. to rewrite a manifest of the tree only if
+    /// [`DirDiffStats::changed`] says so. It isn't called when the diff
+    /// fails.
+    pub fn on_finish(mut self, on_finish: impl FnMut(&DirDiffStats) + 'a) -> Self {
+        self.on_finish = Some(Box::new(on_finish));
+        self
+    }
+
+    /// Call `on_hash` with each destination written and the hash of its
+    /// content, e.g. to fill a manifest. Sources that weren't hashed to be
+    /// compared, such as those of missing destinations, are hashed while
+    /// they're copied, so they're still read only once.
+    ///
+    /// Content written through [`text`](Self::text), a codec, encryption or a
+    /// comparator isn't reported.
+    pub fn on_hash(mut self, on_hash: impl FnMut(&Path, u64) + 'a) -> Self {
+        self.on_hash = Some(Box::new(on_hash));
+        self
+    }
+
+    /// Wait for room in `limit` before diffing each file of a directory
+    /// diff, to bound the files open at once across the diffs sharing it.
+    ///
+    /// A single diff only holds the source and destination of the current
+    /// file open, directory listings being read in full before descending,
+    /// so this only matters for diffs run concurrently.
+    pub fn file_limit(mut self, limit: FileLimit) -> Self {
+        self.file_limit = Some(limit);
+        self
+    }
+
+    /// Run the diff on `fs` instead of the local filesystem, e.g. an
+    /// in-memory or remote store. See [`FileSystem`] for the options that
+    /// don't apply then.
+    pub fn file_system(mut self, fs: &'a dyn FileSystem) -> Self {
+        self.file_system = Some(fs);
+        self
+    }
+
+    /// The text options `to` is compared with, if any.
+    pub(crate) fn text_for(&self, to: &Path) -> Option<&TextOptions> {
+        let strategy = to
+            .extension()
+            .and_then(|extension| self.strategies.get(extension));
+        match strategy {
+            Some(CompareStrategy::Raw) => None,
+            Some(CompareStrategy::Text(text)) => Some(text),
+            None => self.text.as_ref(),
+        }
+    }
+
+    /// The codec of `to` if it's written compressed.
+    pub(crate) fn codec(&self, to: &Path) -> Option<Codec> {
+        if !self.compressed_dest {
+            return None;
+        }
+        Codec::from_path(to)
+    }
+}
+
+impl fmt::Debug for DiffOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiffOptions")
+            .field("retry", &self.retry)
+            .field("text", &self.text)
+            .field("verify_on_match", &self.verify_on_match)
+            .field("skip_dest_modified_after", &self.skip_dest_modified_after)
+            .field("continue_on_error", &self.continue_on_error)
+            .field("case_collisions", &self.case_collisions)
+            .field("dest_symlinks", &self.dest_symlinks)
+            .field("broken_symlinks", &self.broken_symlinks)
+            .field("dest_unreadable", &self.dest_unreadable)
+            .field("write_mode", &self.write_mode)
+            .field("journal", &self.journal)
+            .field("compressed_dest", &self.compressed_dest)
+            .field("dest_prefix", &self.dest_prefix)
+            .field("strip_prefix", &self.strip_prefix)
+            .field("mirror", &self.mirror)
+            .field("sparse", &self.sparse)
+            .field("check_free_space", &self.check_free_space)
+            .field("preserve_file_flags", &self.preserve_file_flags)
+            .field("entry_order", &self.entry_order)
+            .field("backup", &self.backup)
+            .field("dedup", &self.dedup)
+            .field("relative_paths", &self.relative_paths)
+            .field("collect_files", &self.collect_files)
+            .field("assert_unchanged", &self.assert_unchanged)
+            .field("on_source_change", &self.on_source_change)
+            .field("cancel", &self.cancel)
+            .field("max_bytes_written", &self.max_bytes_written)
+            .field("min_size", &self.min_size)
+            .field("max_size", &self.max_size)
+            .field("strategies", &self.strategies)
+            .field("touch_on_skip", &self.touch_on_skip)
+            .field("two_tier", &self.two_tier)
+            .field("sample", &self.sample)
+            .field("preserve_inode", &self.preserve_inode)
+            .field("create_mode", &self.create_mode)
+            .field("skip_locked", &self.skip_locked)
+            .field("comparators", &self.comparators)
+            .field("file_limit", &self.file_limit)
+            .field(
+                "treat_empty_dest_as_present",
+                &self.treat_empty_dest_as_present,
+            )
+            .finish_non_exhaustive()
+    }
+}
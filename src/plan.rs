@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs,
+    hash::Hasher,
+    io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::Context, hash_file, DiffOptions, Operation, Side};
+
+/// A step of [`plan_dir_diff`], holding the destination path it applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "action", content = "path", rename_all = "snake_case")
+)]
+pub enum DiffAction {
+    /// Copy the source to this missing destination.
+    Create(PathBuf),
+    /// Copy the source over this destination holding different content.
+    Overwrite(PathBuf),
+    /// This destination is up to date.
+    Skip(PathBuf),
+    /// This destination has no source counterpart, or is a file where the
+    /// source has a directory or the other way around. Directories are
+    /// yielded once for their whole content.
+    Delete(PathBuf),
+}
+
+/// The actions [`write_on_dir_diff`](crate::write_on_dir_diff) would take to
+/// sync `to` with `from`, plus the deletions making `to` an exact mirror,
+/// without writing anything.
+///
+/// Both trees are walked lazily, one directory at a time, and files are only
+/// hashed when their action is pulled from the iterator. Entries of each
+/// directory come in name order, before the content of its subdirectories.
+/// Errors are yielded for the entry or directory they concern, and the walk
+/// goes on with the next one.
+pub fn plan_dir_diff<H: Hasher + Default>(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> impl Iterator<Item = io::Result<DiffAction>> {
+    Plan::<H> {
+        dirs: vec![(from.as_ref().to_path_buf(), to.as_ref().to_path_buf())],
+        pending: Vec::new(),
+        hasher: PhantomData,
+    }
+}
+
+enum Pending {
+    File(PathBuf, PathBuf),
+    Ready(io::Result<DiffAction>),
+}
+
+struct Plan<H> {
+    /// Directories left to list, as source and destination paths.
+    dirs: Vec<(PathBuf, PathBuf)>,
+    /// Entries of the last listed directory, in reverse order.
+    pending: Vec<Pending>,
+    hasher: PhantomData<H>,
+}
+
+impl<H: Hasher + Default> Iterator for Plan<H> {
+    type Item = io::Result<DiffAction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.pending.pop() {
+                Some(Pending::Ready(action)) => return Some(action),
+                Some(Pending::File(from, to)) => return Some(file_action::<H>(&from, to)),
+                None => {}
+            }
+            let (from, to) = self.dirs.pop()?;
+            if let Err(e) = self.list(&from, &to) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<H> Plan<H> {
+    fn list(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let from_names = names(from).context(Operation::ReadDir, Side::Source, from)?;
+        let to_names = match names(to) {
+            Ok(names) => names,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e).context(Operation::ReadDir, Side::Destination, to),
+        };
+        let mut entries = Vec::new();
+        let mut subdirs = Vec::new();
+        for name in from_names.union(&to_names) {
+            let from_path = from.join(name);
+            let to_path = to.join(name);
+            if !from_names.contains(name) {
+                entries.push(Pending::Ready(Ok(DiffAction::Delete(to_path))));
+                continue;
+            }
+            let to_exists = to_names.contains(name);
+            if from_path.is_dir() {
+                if to_exists && !to_path.is_dir() {
+                    entries.push(Pending::Ready(Ok(DiffAction::Delete(to_path.clone()))));
+                }
+                subdirs.push((from_path, to_path));
+            } else {
+                if to_exists && to_path.is_dir() {
+                    entries.push(Pending::Ready(Ok(DiffAction::Delete(to_path.clone()))));
+                }
+                entries.push(Pending::File(from_path, to_path));
+            }
+        }
+        self.pending.extend(entries.into_iter().rev());
+        self.dirs.extend(subdirs.into_iter().rev());
+        Ok(())
+    }
+}
+
+fn names(dir: &Path) -> io::Result<BTreeSet<OsString>> {
+    fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect()
+}
+
+fn file_action<H: Hasher + Default>(from: &Path, to: PathBuf) -> io::Result<DiffAction> {
+    let options = &mut DiffOptions::default();
+    if to.is_dir() {
+        return Ok(DiffAction::Create(to));
+    }
+    let to_hash = match hash_file::<H>(&to, options) {
+        Ok(to_hash) => to_hash,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DiffAction::Create(to)),
+        Err(e) => return Err(e).context(Operation::Read, Side::Destination, &to),
+    };
+    let from_hash = hash_file::<H>(from, options).context(Operation::Read, Side::Source, from)?;
+    Ok(if from_hash == to_hash {
+        DiffAction::Skip(to)
+    } else {
+        DiffAction::Overwrite(to)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_dir_diff() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        let to_dir = tempdir()?;
+        let (from, to) = (from_dir.path(), to_dir.path());
+        fs::create_dir_all(from.join("sub"))?;
+        fs::write(from.join("new.txt"), "new")?;
+        fs::write(from.join("same.txt"), "same")?;
+        fs::write(from.join("changed.txt"), "changed")?;
+        fs::write(from.join("sub/nested.txt"), "nested")?;
+        fs::create_dir_all(to.join("gone"))?;
+        fs::write(to.join("same.txt"), "same")?;
+        fs::write(to.join("changed.txt"), "old")?;
+        fs::write(to.join("extra.txt"), "extra")?;
+
+        let actions = plan_dir_diff::<FxHasher>(from, to).collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(
+            actions,
+            [
+                DiffAction::Overwrite(to.join("changed.txt")),
+                DiffAction::Delete(to.join("extra.txt")),
+                DiffAction::Delete(to.join("gone")),
+                DiffAction::Create(to.join("new.txt")),
+                DiffAction::Skip(to.join("same.txt")),
+                DiffAction::Create(to.join("sub/nested.txt")),
+            ]
+        );
+        // Nothing was written.
+        assert!(!to.join("new.txt").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::{io, thread, time::Duration};
+
+use crate::DiffError;
+
+/// How many times a failed read or copy is retried before giving up.
+///
+/// Only errors accepted by the classifier are retried, waiting `backoff`
+/// before the first retry and doubling the wait after each further attempt.
+/// Every other error is returned immediately.
+///
+/// The default classifier retries both [`RetryPolicy::is_transient`] and
+/// [`RetryPolicy::is_locked`] errors.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    classifier: fn(&io::Error) -> bool,
+}
+
+impl RetryPolicy {
+    /// Never retry, fail on the first error.
+    pub const NONE: Self = Self {
+        attempts: 0,
+        backoff: Duration::ZERO,
+        classifier: Self::is_transient_error,
+    };
+
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        Self {
+            attempts,
+            backoff,
+            ..Self::NONE
+        }
+    }
+
+    /// Replace the function deciding which errors are worth retrying.
+    pub fn with_classifier(mut self, classifier: fn(&io::Error) -> bool) -> Self {
+        self.classifier = classifier;
+        self
+    }
+
+    /// The error kinds retried by default: `Interrupted`, `WouldBlock` and `TimedOut`.
+    pub fn is_transient(kind: io::ErrorKind) -> bool {
+        matches!(
+            kind,
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    }
+
+    /// Whether `error` is a Windows sharing or lock violation, raised when
+    /// another process has the file open. These locks are often momentary.
+    pub fn is_locked(error: &io::Error) -> bool {
+        let error = DiffError::from_io(error).map_or(error, |e| &e.error);
+        // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION.
+        cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33))
+    }
+
+    fn is_transient_error(error: &io::Error) -> bool {
+        Self::is_transient(error.kind()) || Self::is_locked(error)
+    }
+
+    pub(crate) fn run<T>(&self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(e) if attempt < self.attempts && (self.classifier)(&e) => {
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_transient_until_success() {
+        let policy = RetryPolicy::new(3, Duration::ZERO);
+        let mut calls = 0;
+        let result = policy.run(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::Interrupted))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_retry_gives_up_after_attempts() {
+        let policy = RetryPolicy::new(2, Duration::ZERO);
+        let mut calls = 0;
+        let result: io::Result<()> = policy.run(|| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::TimedOut))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_retry_permanent_fails_immediately() {
+        let policy = RetryPolicy::new(5, Duration::ZERO);
+        for kind in [io::ErrorKind::NotFound, io::ErrorKind::PermissionDenied] {
+            let mut calls = 0;
+            let result: io::Result<()> = policy.run(|| {
+                calls += 1;
+                Err(io::Error::from(kind))
+            });
+            assert_eq!(result.unwrap_err().kind(), kind);
+            assert_eq!(calls, 1);
+        }
+    }
+
+    #[test]
+    fn test_is_locked() {
+        let locked = io::Error::from_raw_os_error(32);
+        assert_eq!(RetryPolicy::is_locked(&locked), cfg!(windows));
+        let locked: io::Error = DiffError {
+            operation: crate::Operation::Copy,
+            side: crate::Side::Destination,
+            path: "out.dll".into(),
+            counterpart: None,
+            error: io::Error::from_raw_os_error(33),
+        }
+        .into();
+        assert_eq!(RetryPolicy::is_locked(&locked), cfg!(windows));
+        assert!(!RetryPolicy::is_locked(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+    }
+}
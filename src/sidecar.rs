@@ -0,0 +1,60 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Maps a destination to the sidecar file holding its hash, set through
+/// [`DiffOptions::sidecar`](crate::DiffOptions::sidecar).
+pub(crate) type SidecarPath<'a> = Box<dyn FnMut(&Path) -> PathBuf + 'a>;
+
+/// The size and modification time of `to` in nanoseconds, which the hash
+/// recorded in a sidecar is valid for.
+fn stamp(to: &Path) -> io::Result<(u64, u128)> {
+    let metadata = fs::metadata(to)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |mtime| mtime.as_nanos());
+    Ok((metadata.len(), mtime))
+}
+
+/// The hash of `to` recorded in `sidecar`, if it's there and `to` wasn't
+/// modified since.
+pub(crate) fn read(sidecar: &Path, to: &Path) -> Option<u64> {
+    let content = fs::read_to_string(sidecar).ok()?;
+    let mut fields = content.split_whitespace();
+    let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let len = fields.next()?.parse().ok()?;
+    let mtime = fields.next()?.parse().ok()?;
+    (stamp(to).ok()? == (len, mtime)).then_some(hash)
+}
+
+/// Record that `to`, as it is now, hashes to `hash`.
+pub(crate) fn write(sidecar: &Path, to: &Path, hash: u64) -> io::Result<()> {
+    let (len, mtime) = stamp(to)?;
+    fs::write(sidecar, format!("{hash:016x} {len} {mtime}\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sidecar_goes_stale() -> io::Result<()> {
+        let dir = tempdir()?;
+        let to = dir.path().join("data.bin");
+        let sidecar = dir.path().join("data.bin.sha");
+        assert_eq!(read(&sidecar, &to), None);
+
+        fs::write(&to, "data")?;
+        write(&sidecar, &to, 0xabc)?;
+        assert_eq!(read(&sidecar, &to), Some(0xabc));
+
+        fs::write(&to, "changed")?;
+        assert_eq!(read(&sidecar, &to), None);
+
+        Ok(())
+    }
+}
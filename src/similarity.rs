@@ -0,0 +1,99 @@
+//! Similarity of two versions of a file, estimated from the chunks they
+//! share like content-defined deduplication does.
+
+use std::{collections::HashSet, path::Path};
+
+/// Reports the similarity in percent of a destination about to be
+/// overwritten to its source, set through
+/// [`DiffOptions::on_similarity`](crate::DiffOptions::on_similarity).
+pub(crate) type OnSimilarity<'a> = Box<dyn FnMut(&Path, u8) + 'a>;
+
+/// Chunk boundaries are where the rolling hash has these bits unset, about
+/// every 1 KiB.
+const BOUNDARY_MASK: u64 = (1 << 10) - 1;
+/// Chunks are at least this long, so runs of equal bytes don't make a
+/// boundary at each of them.
+const MIN_CHUNK: usize = 64;
+
+/// Random values per byte for the gear rolling hash, from splitmix64.
+const GEAR: [u64; 256] = {
+    let mut gear = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        gear[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    gear
+};
+
+/// Split `bytes` where a gear hash, rolling over the last 64 bytes, hits a
+/// boundary, so that an insertion only changes the chunks around it.
+fn chunks(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = bytes;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut hash: u64 = 0;
+        let mut end = rest.len();
+        for (i, &b) in rest.iter().enumerate() {
+            hash = (hash << 1).wrapping_add(GEAR[b as usize]);
+            if i + 1 >= MIN_CHUNK && hash & BOUNDARY_MASK == 0 {
+                end = i + 1;
+                break;
+            }
+        }
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// The share of `from` in percent made of chunks also found in `to`.
+pub(crate) fn similarity(from: &[u8], to: &[u8]) -> u8 {
+    if from.is_empty() {
+        return if to.is_empty() { 100 } else { 0 };
+    }
+    let to_chunks: HashSet<&[u8]> = chunks(to).collect();
+    let shared: usize = chunks(from)
+        .filter(|chunk| to_chunks.contains(chunk))
+        .map(<[u8]>::len)
+        .sum();
+    (shared * 100 / from.len()) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(len: usize) -> Vec<u8> {
+        let mut state: u32 = 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_similarity() {
+        let to = content(64 * 1024);
+        assert_eq!(similarity(&to, &to), 100);
+        assert_eq!(similarity(&content(0), &to), 0);
+
+        let mut from = to.clone();
+        from.splice(30_000..30_000, *b"inserted");
+        assert!(similarity(&from, &to) >= 90);
+
+        let other: Vec<u8> = to.iter().map(|b| b ^ 0xFF).collect();
+        assert!(similarity(&other, &to) <= 5);
+    }
+}
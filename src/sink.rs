@@ -0,0 +1,230 @@
+use std::{
+    fs::{self, File},
+    hash::Hasher,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    error::Context, hash_file, DiffOptions, HashWriter, Operation, Side, SkipReason, Tee,
+    WriteOutcome,
+};
+
+/// An [`io::Write`] streaming generated content into a temporary file next
+/// to `to`, hashing it along the way, for producers that shouldn't buffer
+/// their whole output.
+///
+/// [`finish`](Self::finish) then moves it over `to` only if the content is
+/// different, and [`discard`](Self::discard) throws it away, as does
+/// dropping the sink.
+pub struct DiffSink<H: Hasher> {
+    to: PathBuf,
+    temp: PathBuf,
+    preserve_inode: bool,
+    tee: Option<Tee<BufWriter<File>, H>>,
+    len: u64,
+}
+
+/// Tells apart the temporary files of the sinks of this process.
+static SINKS: AtomicU64 = AtomicU64::new(0);
+
+impl<H: Hasher + Default> DiffSink<H> {
+    pub fn new(to: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::with_options(to, &DiffOptions::default())
+    }
+
+    /// A sink following `options`, of which only
+    /// [`DiffOptions::preserve_inode`] applies: an existing destination is
+    /// then rewritten in place instead of replaced by the temporary file.
+    pub fn with_options(to: impl Into<PathBuf>, options: &DiffOptions<'_>) -> io::Result<Self> {
+        let to = to.into();
+        let (temp, file) = loop {
+            let mut name = to.file_name().unwrap_or_default().to_os_string();
+            let sink = SINKS.fetch_add(1, Ordering::Relaxed);
+            name.push(format!(".{}.{sink}.wod-tmp", process::id()));
+            let temp = to.with_file_name(name);
+            // Never truncate a file left by another process that had the
+            // same pid.
+            match File::options().write(true).create_new(true).open(&temp) {
+                Ok(file) => break (temp, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e).context(Operation::Write, Side::Destination, &temp),
+            }
+        };
+        Ok(Self {
+            to,
+            temp,
+            preserve_inode: options.preserve_inode,
+            tee: Some(Tee(BufWriter::new(file), HashWriter::new(H::default()))),
+            len: 0,
+        })
+    }
+
+    /// Move the content written over the destination, or copy it into the
+    /// destination with [`DiffOptions::preserve_inode`], unless it already
+    /// holds the same, removing the temporary file either way.
+    pub fn finish(mut self) -> io::Result<WriteOutcome> {
+        let (to, temp) = (&self.to, &self.temp);
+        let Tee(writer, hasher) = self.tee.take().unwrap();
+        writer.into_inner().map_err(|e| e.into_error()).context(
+            Operation::Write,
+            Side::Destination,
+            temp,
+        )?;
+        let from_hash = hasher.finish();
+        let outcome = match fs::metadata(to) {
+            Ok(metadata) => {
+                let same = metadata.len() == self.len
+                    && hash_file::<H>(to, &mut DiffOptions::default()).context(
+                        Operation::Read,
+                        Side::Destination,
+                        to,
+                    )? == from_hash;
+                if same {
+                    fs::remove_file(temp).context(Operation::Write, Side::Destination, temp)?;
+                    return Ok(WriteOutcome::Skipped(SkipReason::HashMatch));
+                }
+                if self.preserve_inode {
+                    rewrite(temp, to)?;
+                    fs::remove_file(temp).context(Operation::Write, Side::Destination, temp)?;
+                    return Ok(WriteOutcome::Overwritten);
+                }
+                // Keep the permissions of the file being replaced.
+                fs::set_permissions(temp, metadata.permissions()).context(
+                    Operation::Write,
+                    Side::Destination,
+                    temp,
+                )?;
+                WriteOutcome::Overwritten
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => WriteOutcome::Created,
+            Err(e) => return Err(e).context(Operation::Metadata, Side::Destination, to),
+        };
+        fs::rename(temp, to).context(Operation::Write, Side::Destination, to)?;
+        Ok(outcome)
+    }
+
+    /// Throw away the content written, leaving the destination alone.
+    pub fn discard(mut self) -> io::Result<()> {
+        drop(self.tee.take());
+        fs::remove_file(&self.temp).context(Operation::Write, Side::Destination, &self.temp)
+    }
+
+    /// The destination the content is written to.
+    pub fn path(&self) -> &Path {
+        &self.to
+    }
+}
+
+/// Truncate `to` and copy `temp` into it, keeping its inode.
+fn rewrite(temp: &Path, to: &Path) -> io::Result<()> {
+    let mut from = File::open(temp).context(Operation::Read, Side::Destination, temp)?;
+    let mut to_file = File::options()
+        .write(true)
+        .truncate(true)
+        .open(to)
+        .context(Operation::Write, Side::Destination, to)?;
+    io::copy(&mut from, &mut to_file).context(Operation::Write, Side::Destination, to)?;
+    Ok(())
+}
+
+impl<H: Hasher> Write for DiffSink<H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.tee.as_mut().unwrap().write(buf)?;
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tee.as_mut().unwrap().flush()
+    }
+}
+
+impl<H: Hasher> Drop for DiffSink<H> {
+    fn drop(&mut self) {
+        if self.tee.take().is_some() {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_diff_sink() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("out.txt");
+        let temp_files = || -> io::Result<usize> { Ok(fs::read_dir(to_dir.path())?.count()) };
+
+        let mut sink = DiffSink::<FxHasher>::new(&to_path)?;
+        write!(sink, "hello {}", 42)?;
+        assert_eq!(sink.finish()?, WriteOutcome::Created);
+        assert_eq!(fs::read_to_string(&to_path)?, "hello 42");
+
+        let mut sink = DiffSink::<FxHasher>::new(&to_path)?;
+        sink.write_all(b"hello ")?;
+        sink.write_all(b"42")?;
+        assert_eq!(sink.finish()?, WriteOutcome::Skipped(SkipReason::HashMatch));
+        assert_eq!(temp_files()?, 1);
+
+        let mut sink = DiffSink::<FxHasher>::new(&to_path)?;
+        sink.write_all(b"discarded")?;
+        sink.discard()?;
+        let mut sink = DiffSink::<FxHasher>::new(&to_path)?;
+        sink.write_all(b"dropped")?;
+        drop(sink);
+        assert_eq!(fs::read_to_string(&to_path)?, "hello 42");
+        assert_eq!(temp_files()?, 1);
+
+        let mut sink = DiffSink::<FxHasher>::new(&to_path)?;
+        sink.write_all(b"changed")?;
+        assert_eq!(sink.finish()?, WriteOutcome::Overwritten);
+        assert_eq!(fs::read_to_string(&to_path)?, "changed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_sinks_same_destination() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("out.txt");
+
+        let mut first = DiffSink::<FxHasher>::new(&to_path)?;
+        let mut second = DiffSink::<FxHasher>::new(&to_path)?;
+        first.write_all(b"first")?;
+        second.write_all(b"second")?;
+        assert_eq!(first.finish()?, WriteOutcome::Created);
+        assert_eq!(second.finish()?, WriteOutcome::Overwritten);
+        assert_eq!(fs::read_to_string(&to_path)?, "second");
+        assert_eq!(fs::read_dir(to_dir.path())?.count(), 1);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_diff_sink_preserve_inode() -> io::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let to_dir = tempdir()?;
+        let to_path = to_dir.path().join("app.log");
+        fs::write(&to_path, "old")?;
+        let inode = fs::metadata(&to_path)?.ino();
+
+        let options = DiffOptions::new().preserve_inode(true);
+        let mut sink = DiffSink::<FxHasher>::with_options(&to_path, &options)?;
+        sink.write_all(b"new content")?;
+        assert_eq!(sink.finish()?, WriteOutcome::Overwritten);
+        assert_eq!(fs::read_to_string(&to_path)?, "new content");
+        assert_eq!(fs::metadata(&to_path)?.ino(), inode);
+        assert_eq!(fs::read_dir(to_dir.path())?.count(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use std::{io, path::Path};
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`,
+/// `None` where this isn't supported.
+#[cfg(unix)]
+pub(crate) fn available(dir: &Path) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid C string and `stat` is only read once
+    // statvfs succeeded and initialized it.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn available(_dir: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
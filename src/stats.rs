@@ -0,0 +1,278 @@
+use std::path::{Path, PathBuf};
+
+use crate::DiffError;
+
+/// Why a destination file was left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SkipReason {
+    /// Source and destination hashes matched.
+    HashMatch,
+    /// Source and destination had the same size, then matching hashes.
+    SizeAndHashMatch,
+    /// Source and destination are both empty, nothing was hashed.
+    BothEmpty,
+    /// The destination differs but was modified after
+    /// [`DiffOptions::skip_dest_modified_after`](crate::DiffOptions::skip_dest_modified_after).
+    DestNewer,
+    /// Both sides changed since the baseline and the conflict callback chose
+    /// [`ConflictAction::Skip`](crate::ConflictAction::Skip).
+    Conflict,
+    /// Another source name differing only by case was kept, see
+    /// [`CaseCollisions::Canonical`](crate::CaseCollisions::Canonical).
+    CaseCollision,
+    /// The destination exists and
+    /// [`WriteMode::CreateIfAbsent`](crate::WriteMode::CreateIfAbsent) is set.
+    DestExists,
+    /// Source and destination share the same inode, e.g. hardlinked, so
+    /// neither was read.
+    SameInode,
+    /// An interrupted run of the same session already completed it, see
+    /// [`DiffOptions::journal`](crate::DiffOptions::journal).
+    Resumed,
+    /// The comparator given to
+    /// [`DiffOptions::compare`](crate::DiffOptions::compare) found both sides
+    /// equal.
+    Equivalent,
+    /// The source is a symlink to nothing, see
+    /// [`DiffOptions::broken_symlinks`](crate::DiffOptions::broken_symlinks).
+    /// With [`BrokenSymlinks::Recreate`](crate::BrokenSymlinks::Recreate), the
+    /// destination is already the same link.
+    BrokenSymlink,
+    /// Source and destination had the same size and the same ends, see
+    /// [`DiffOptions::sample`](crate::DiffOptions::sample).
+    Sampled,
+    /// The source hash was in the set of known hashes given to
+    /// [`write_on_bytes_diff_unless_known`](crate::write_on_bytes_diff_unless_known).
+    KnownHash,
+    /// The source is outside the sizes given to
+    /// [`DiffOptions::min_size`](crate::DiffOptions::min_size) and
+    /// [`DiffOptions::max_size`](crate::DiffOptions::max_size).
+    Size,
+    /// The file is open in another process, see
+    /// [`DiffOptions::skip_locked`](crate::DiffOptions::skip_locked).
+    Locked,
+    /// The destination is empty and
+    /// [`DiffOptions::treat_empty_dest_as_present`](crate::DiffOptions::treat_empty_dest_as_present)
+    /// is set.
+    EmptyDest,
+}
+
+/// What happened to a single destination file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "kind", content = "reason", rename_all = "snake_case")
+)]
+pub enum WriteOutcome {
+    /// The destination didn't exist and was written.
+    Created,
+    /// The destination existed with different content and was rewritten.
+    Overwritten,
+    /// The destination wasn't written.
+    Skipped(SkipReason),
+}
+
+/// The state of a destination after [`upsert_bytes`](crate::upsert_bytes),
+/// e.g. to build a manifest entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct UpsertReport {
+    pub outcome: WriteOutcome,
+    /// Hash of the destination content, the same as the source's.
+    pub hash: u64,
+    /// Size of the destination in bytes.
+    pub len: u64,
+}
+
+/// How much a directory diff saves over copying everything, from
+/// [`estimate_write_savings`](crate::estimate_write_savings).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct SavingsReport {
+    /// Size of all source files, what copying everything would write.
+    pub total_source_bytes: u64,
+    /// Size of the source files whose destination is missing or differs.
+    pub bytes_that_would_change: u64,
+}
+
+impl SavingsReport {
+    /// Share of the source bytes the diff doesn't write, in percent, 0 for
+    /// an empty source.
+    pub fn saved_percent(&self) -> f64 {
+        if self.total_source_bytes == 0 {
+            return 0.0;
+        }
+        let saved = self.total_source_bytes - self.bytes_that_would_change;
+        saved as f64 * 100.0 / self.total_source_bytes as f64
+    }
+}
+
+/// Differences between a tree and its manifest, from
+/// [`verify_against_manifest`](crate::verify_against_manifest). Paths are
+/// relative to the root of the tree, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct VerifyReport {
+    /// Files whose content doesn't hash to the manifest's.
+    pub mismatched: Vec<PathBuf>,
+    /// Files of the manifest that aren't there.
+    pub missing: Vec<PathBuf>,
+    /// Files that aren't in the manifest.
+    pub extra: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    /// Whether the tree matches the manifest exactly.
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// Summary of a directory diff.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct DirDiffStats {
+    pub created: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+    /// Destinations deleted by
+    /// [`DiffOptions::mirror`](crate::DiffOptions::mirror).
+    pub deleted: Vec<PathBuf>,
+    /// Destination directories that didn't exist and were created, parents
+    /// first, e.g. to remove them if a later step fails.
+    pub created_dirs: Vec<PathBuf>,
+    /// Destinations left alone because they were modified after the cutoff.
+    pub dest_newer: Vec<PathBuf>,
+    /// Destination files written or found up to date, in walk order, when
+    /// [`DiffOptions::collect_files`](crate::DiffOptions::collect_files) is
+    /// set.
+    pub files: Vec<PathBuf>,
+    /// Failed entries, when continuing on error.
+    pub errors: Vec<DiffError>,
+    /// Whether the diff stopped early through
+    /// [`DiffOptions::cancel`](crate::DiffOptions::cancel), the other fields
+    /// covering what was done until then.
+    pub cancelled: bool,
+    /// Whether the diff stopped early because
+    /// [`DiffOptions::max_bytes_written`](crate::DiffOptions::max_bytes_written)
+    /// was reached.
+    pub quota_reached: bool,
+    /// Files whose hashes matched but whose content differed, found with
+    /// [`DiffOptions::verify_on_match`](crate::DiffOptions::verify_on_match).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hash_collisions: usize,
+    /// BLAKE3 digests of the source files, by destination path relative to
+    /// the root, when
+    /// [`DiffOptions::b3sums`](crate::DiffOptions::b3sums) is set.
+    #[cfg(feature = "blake3")]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub b3sums: Vec<(PathBuf, [u8; 32])>,
+    /// IO operations performed, to compare configurations.
+    #[cfg(feature = "instrument")]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub io: crate::IoCounters,
+    /// Time spent per phase, to see where a run goes.
+    #[cfg(feature = "timing")]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timings: crate::Timings,
+}
+
+impl DirDiffStats {
+    pub(crate) fn record(&mut self, to: &Path, outcome: WriteOutcome) {
+        match outcome {
+            WriteOutcome::Created => self.created += 1,
+            WriteOutcome::Overwritten => self.overwritten += 1,
+            WriteOutcome::Skipped(reason) => {
+                self.skipped += 1;
+                if reason == SkipReason::DestNewer {
+                    self.dest_newer.push(to.to_path_buf());
+                }
+            }
+        }
+    }
+
+    /// Number of destination files written.
+    pub fn written(&self) -> usize {
+        self.created + self.overwritten
+    }
+
+    /// Whether the destination tree changed at all, with files written or
+    /// deleted or directories created, e.g. to only regenerate a manifest
+    /// of the tree when needed.
+    pub fn changed(&self) -> bool {
+        self.written() > 0 || !self.deleted.is_empty() || !self.created_dirs.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_to_json() {
+        let mut stats = DirDiffStats::default();
+        stats.record(Path::new("a"), WriteOutcome::Created);
+        stats.record(Path::new("b"), WriteOutcome::Skipped(SkipReason::HashMatch));
+        stats.record(Path::new("c"), WriteOutcome::Skipped(SkipReason::DestNewer));
+        let json = serde_json::to_string(&stats).unwrap();
+        #[cfg(feature = "blake3")]
+        let json = json.replace(r#","b3sums":[]"#, "");
+        #[cfg(feature = "instrument")]
+        let json = json.replace(
+            r#","io":{"opens":0,"reads":0,"writes":0,"metadata":0,"copies":0,"read_dirs":0}"#,
+            "",
+        );
+        #[cfg(feature = "timing")]
+        let json = json.replace(
+            r#","timings":{"hash":{"secs":0,"nanos":0},"copy":{"secs":0,"nanos":0},"metadata":{"secs":0,"nanos":0}}"#,
+            "",
+        );
+        assert_eq!(
+            json,
+            r#"{"created":1,"overwritten":0,"skipped":2,"deleted":[],"created_dirs":[],"dest_newer":["c"],"files":[],"errors":[],"cancelled":false,"quota_reached":false,"hash_collisions":0}"#
+        );
+    }
+
+    #[test]
+    fn test_outcome_json_round_trip() {
+        let outcome = WriteOutcome::Skipped(SkipReason::SizeAndHashMatch);
+        let json = serde_json::to_string(&outcome).unwrap();
+        assert_eq!(json, r#"{"kind":"skipped","reason":"size_and_hash_match"}"#);
+        assert_eq!(
+            serde_json::from_str::<WriteOutcome>(&json).unwrap(),
+            outcome
+        );
+        assert_eq!(
+            serde_json::to_string(&WriteOutcome::Created).unwrap(),
+            r#"{"kind":"created"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&SkipReason::SameInode).unwrap(),
+            r#""same_inode""#
+        );
+
+        let mut stats = DirDiffStats::default();
+        stats.record(Path::new("a"), WriteOutcome::Overwritten);
+        stats.errors.push(DiffError {
+            operation: crate::Operation::ReadDir,
+            side: crate::Side::Source,
+            path: "b".into(),
+            counterpart: None,
+            error: std::io::Error::other("denied"),
+        });
+        let json = serde_json::to_string(&stats).unwrap();
+        let back: DirDiffStats = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.overwritten, 1);
+        assert_eq!(back.errors[0].operation, crate::Operation::ReadDir);
+        assert_eq!(back.errors[0].path, Path::new("b"));
+        assert_eq!(back.errors[0].error.to_string(), "denied");
+        assert!(json.contains(r#""operation":"read_dir","side":"source""#));
+    }
+}
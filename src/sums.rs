@@ -0,0 +1,101 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::Write as _,
+    io,
+    path::{Component, Path},
+};
+
+use crate::{write_on_bytes_diff_with_options, DiffOptions, DirDiffStats, WriteOutcome};
+
+/// Write a checksums file at `sums` listing the BLAKE3 digests a directory
+/// diff collected with [`DiffOptions::b3sums`], in the `<hex hash>  <path>`
+/// format of `b3sum`, so that running `b3sum -c` from the destination root
+/// verifies the tree the diff left.
+///
+/// Paths are `/` separated and sorted. `sums` is written through a diff, so
+/// it only changes when the tree did.
+pub fn write_b3sums(stats: &DirDiffStats, sums: impl AsRef<Path>) -> io::Result<WriteOutcome> {
+    let mut digests: Vec<_> = stats.b3sums.iter().collect();
+    digests.sort();
+    let mut content = String::new();
+    for (rel, digest) in digests {
+        let hash = blake3::Hash::from(*digest).to_hex();
+        let name = sums_name(rel).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("non UTF-8 path {}", rel.display()),
+            )
+        })?;
+        // Like coreutils, a leading backslash marks escaped names.
+        if name.contains(['\\', '\n']) {
+            let name = name.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(content, "\\{hash}  {name}").unwrap();
+        } else {
+            writeln!(content, "{hash}  {name}").unwrap();
+        }
+    }
+    // Any hasher does to tell whether the checksums changed.
+    let options = &mut DiffOptions::default();
+    write_on_bytes_diff_with_options::<DefaultHasher>(content.as_bytes(), sums, options)
+}
+
+/// `rel` joined with `/` whatever the platform.
+fn sums_name(rel: &Path) -> Option<String> {
+    let names: Option<Vec<_>> = rel
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    Some(names?.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_on_dir_diff_with_options;
+    use rustc_hash::FxHasher;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_b3sums() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::create_dir(from_dir.path().join("sub"))?;
+        fs::write(from_dir.path().join("sub/b.txt"), "b")?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("a.txt"), "a")?;
+        let sums_dir = tempdir()?;
+        let sums = sums_dir.path().join("B3SUMS");
+
+        let mut options = DiffOptions::new().b3sums(true);
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        assert_eq!(stats.b3sums.len(), 2);
+        let outcome = write_b3sums(&stats, &sums)?;
+        assert_eq!(outcome, WriteOutcome::Created);
+        assert_eq!(
+            fs::read_to_string(&sums)?,
+            format!(
+                "{}  a.txt\n{}  sub/b.txt\n",
+                blake3::hash(b"a").to_hex(),
+                blake3::hash(b"b").to_hex()
+            )
+        );
+
+        let stats = write_on_dir_diff_with_options::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            &mut options,
+        )?;
+        let outcome = write_b3sums(&stats, &sums)?;
+        assert!(matches!(outcome, WriteOutcome::Skipped(_)));
+
+        Ok(())
+    }
+}
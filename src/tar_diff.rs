@@ -0,0 +1,121 @@
+use std::{
+    hash::Hasher,
+    io::{self, Read},
+    path::Path,
+};
+
+use crate::{
+    diff_into, ensure_relative, error::Context, write_on_bytes_diff_with_options, DiffOptions,
+    DirDiffStats, Operation, Side,
+};
+
+/// [`write_on_tar_diff_with_options`] with the default options.
+pub fn write_on_tar_diff<H: Hasher + Default>(
+    archive: impl Read,
+    to: impl AsRef<Path>,
+) -> io::Result<()> {
+    write_on_tar_diff_with_options::<H>(archive, to, &mut DiffOptions::default()).map(|_| ())
+}
+
+/// [`write_on_dir_diff_with_options`](crate::write_on_dir_diff_with_options)
+/// taking the source tree from a tar archive, read as a stream without
+/// unpacking it first.
+///
+/// Regular files are diffed in memory one at a time and directories are
+/// created, other entries such as links are left out. Entries with absolute
+/// paths or `..` components fail rather than escape `to`. Mirroring and
+/// journals don't apply.
+pub fn write_on_tar_diff_with_options<H: Hasher + Default>(
+    archive: impl Read,
+    to: impl AsRef<Path>,
+    options: &mut DiffOptions<'_>,
+) -> io::Result<DirDiffStats> {
+    let mut archive = tar::Archive::new(archive);
+    diff_into(to.as_ref(), options, |walk, to| {
+        walk.create_dir(to)?;
+        for entry in archive.entries()? {
+            if walk.stopped() {
+                break;
+            }
+            let mut entry = entry?;
+            let rel = entry.path()?.into_owned();
+            let to = to.join(&rel);
+            let diffed = (|| {
+                ensure_relative(&rel)?;
+                let entry_type = entry.header().entry_type();
+                if entry_type.is_dir() {
+                    return walk.create_dir(&to);
+                }
+                if !entry_type.is_file() {
+                    return Ok(());
+                }
+                if let Some(parent) = to.parent() {
+                    walk.create_dir(parent)?;
+                }
+                let mut content = Vec::new();
+                entry
+                    .read_to_end(&mut content)
+                    .context(Operation::Read, Side::Source, &rel)?;
+                let outcome = write_on_bytes_diff_with_options::<H>(&content, &to, walk.options)?;
+                walk.record(&to, outcome);
+                Ok(())
+            })();
+            if let Err(e) = diffed {
+                walk.fail(e, &rel)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SkipReason, WriteOutcome};
+    use rustc_hash::FxHasher;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn archive(files: &[(&str, &str)]) -> io::Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content.as_bytes())?;
+        }
+        builder.into_inner()
+    }
+
+    #[test]
+    fn test_tar_diff() -> io::Result<()> {
+        let to_dir = tempdir()?;
+        fs::write(to_dir.path().join("same.txt"), "same")?;
+        let archive = archive(&[("same.txt", "same"), ("sub/new.txt", "new")])?;
+
+        let mut outcomes = Vec::new();
+        let mut options = DiffOptions::new().on_write(|rel, _, outcome| {
+            outcomes.push((rel.to_owned(), outcome));
+        });
+        let stats =
+            write_on_tar_diff_with_options::<FxHasher>(&archive[..], to_dir.path(), &mut options)?;
+        drop(options);
+
+        assert_eq!((stats.created, stats.skipped), (1, 1));
+        assert_eq!(stats.created_dirs, [to_dir.path().join("sub")]);
+        assert_eq!(
+            outcomes,
+            [
+                (
+                    "same.txt".into(),
+                    WriteOutcome::Skipped(SkipReason::SizeAndHashMatch)
+                ),
+                ("sub/new.txt".into(), WriteOutcome::Created),
+            ]
+        );
+        assert_eq!(fs::read(to_dir.path().join("sub/new.txt"))?, b"new");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use std::borrow::Cow;
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// What to do with a leading UTF-8 BOM when text content is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bom {
+    /// Write the source as is.
+    #[default]
+    Preserve,
+    /// Make sure the written content starts with a BOM.
+    Emit,
+    /// Make sure the written content doesn't start with a BOM.
+    Omit,
+}
+
+/// Comparison rules for text content, enabled through [`DiffOptions::text`](crate::DiffOptions::text).
+///
+/// Both sides are normalized before hashing, so differences removed by the
+/// normalization never trigger a write.
+#[derive(Debug, Clone, Default)]
+pub struct TextOptions {
+    ignore_bom: bool,
+    bom: Bom,
+    trim_trailing_whitespace: bool,
+    write_trimmed: bool,
+    normalize_newlines: bool,
+}
+
+impl TextOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignore a leading UTF-8 BOM when comparing.
+    pub fn ignore_bom(mut self, ignore_bom: bool) -> Self {
+        self.ignore_bom = ignore_bom;
+        self
+    }
+
+    /// Choose whether written content carries a BOM.
+    pub fn bom(mut self, bom: Bom) -> Self {
+        self.bom = bom;
+        self
+    }
+
+    /// Ignore spaces and tabs at the end of each line when comparing.
+    pub fn trim_trailing_whitespace(mut self, trim_trailing_whitespace: bool) -> Self {
+        self.trim_trailing_whitespace = trim_trailing_whitespace;
+        self
+    }
+
+    /// Write the source with trailing whitespace trimmed instead of as is.
+    pub fn write_trimmed(mut self, write_trimmed: bool) -> Self {
+        self.write_trimmed = write_trimmed;
+        self
+    }
+
+    /// Treat `\r\n` line endings as `\n` when comparing.
+    pub fn normalize_newlines(mut self, normalize_newlines: bool) -> Self {
+        self.normalize_newlines = normalize_newlines;
+        self
+    }
+
+    /// The content used for comparison.
+    pub(crate) fn normalize<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        let bytes = if self.ignore_bom {
+            strip_bom(bytes)
+        } else {
+            bytes
+        };
+        let bytes = if self.trim_trailing_whitespace {
+            Cow::Owned(trim_trailing_whitespace(bytes))
+        } else {
+            Cow::Borrowed(bytes)
+        };
+        if self.normalize_newlines && bytes.windows(2).any(|pair| pair == b"\r\n") {
+            Cow::Owned(lf_newlines(&bytes))
+        } else {
+            bytes
+        }
+    }
+
+    /// The content written to the destination.
+    pub(crate) fn output<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        let bytes = if self.write_trimmed {
+            Cow::Owned(trim_trailing_whitespace(bytes))
+        } else {
+            Cow::Borrowed(bytes)
+        };
+        match self.bom {
+            Bom::Emit if !bytes.starts_with(BOM) => Cow::Owned([BOM, &bytes].concat()),
+            Bom::Omit if bytes.starts_with(BOM) => match bytes {
+                Cow::Borrowed(bytes) => Cow::Borrowed(strip_bom(bytes)),
+                Cow::Owned(bytes) => Cow::Owned(strip_bom(&bytes).to_vec()),
+            },
+            _ => bytes,
+        }
+    }
+}
+
+fn strip_bom(bytes: &[u8]) -> &[u8] {
+    bytes.strip_prefix(BOM).unwrap_or(bytes)
+}
+
+/// Replace every `\r\n` by `\n`.
+fn lf_newlines(bytes: &[u8]) -> Vec<u8> {
+    let mut lf = Vec::with_capacity(bytes.len());
+    for (i, &b) in bytes.iter().enumerate() {
+        if b != b'\r' || bytes.get(i + 1) != Some(&b'\n') {
+            lf.push(b);
+        }
+    }
+    lf
+}
+
+/// Remove spaces and tabs before every line ending, keeping `\r\n` intact.
+fn trim_trailing_whitespace(bytes: &[u8]) -> Vec<u8> {
+    let mut trimmed = Vec::with_capacity(bytes.len());
+    for (i, line) in bytes.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            trimmed.push(b'\n');
+        }
+        let (line, cr) = match line.strip_suffix(b"\r") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let end = line
+            .iter()
+            .rposition(|b| !matches!(b, b' ' | b'\t'))
+            .map_or(0, |i| i + 1);
+        trimmed.extend_from_slice(&line[..end]);
+        if cr {
+            trimmed.push(b'\r');
+        }
+    }
+    trimmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_ignores_bom() {
+        let text = TextOptions::new().ignore_bom(true);
+        assert_eq!(&*text.normalize(b"\xEF\xBB\xBFhello"), b"hello");
+        assert_eq!(&*text.normalize(b"hello"), b"hello");
+
+        let text = TextOptions::new();
+        assert_eq!(&*text.normalize(b"\xEF\xBB\xBFhello"), b"\xEF\xBB\xBFhello");
+    }
+
+    #[test]
+    fn test_output_bom_policy() {
+        let with = b"\xEF\xBB\xBFhello";
+        let without = b"hello";
+
+        let text = TextOptions::new().bom(Bom::Emit);
+        assert_eq!(&*text.output(with), with);
+        assert_eq!(&*text.output(without), with);
+
+        let text = TextOptions::new().bom(Bom::Omit);
+        assert_eq!(&*text.output(with), without);
+        assert_eq!(&*text.output(without), without);
+
+        let text = TextOptions::new();
+        assert_eq!(&*text.output(with), with);
+        assert_eq!(&*text.output(without), without);
+    }
+
+    #[test]
+    fn test_trim_trailing_whitespace() {
+        let text = TextOptions::new().trim_trailing_whitespace(true);
+        assert_eq!(&*text.normalize(b"a  \nb\t\r\n  \nc "), b"a\nb\r\n\nc");
+        assert_eq!(&*text.output(b"a  \n"), b"a  \n");
+
+        let text = text.write_trimmed(true).bom(Bom::Emit);
+        assert_eq!(&*text.output(b"a  \n"), b"\xEF\xBB\xBFa\n");
+    }
+
+    #[test]
+    fn test_normalize_newlines() {
+        let text = TextOptions::new().normalize_newlines(true);
+        assert_eq!(&*text.normalize(b"a\r\nb\rc\n"), b"a\nb\rc\n");
+        assert_eq!(&*text.output(b"a\r\n"), b"a\r\n");
+    }
+}
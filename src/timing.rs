@@ -0,0 +1,74 @@
+//! Time spent per phase, only measured with the `timing` feature so no
+//! clock is read otherwise.
+
+#[cfg(feature = "timing")]
+use std::{cell::Cell, time::Duration, time::Instant};
+
+/// Time a directory diff spent in each phase, see
+/// [`DirDiffStats::timings`](crate::DirDiffStats::timings).
+#[cfg(feature = "timing")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timings {
+    /// Reading and hashing content to compare it.
+    pub hash: Duration,
+    /// Writing and copying destinations.
+    pub copy: Duration,
+    /// Metadata lookups, including existence checks.
+    pub metadata: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Phase {
+    Hash,
+    Copy,
+    Metadata,
+}
+
+#[cfg(feature = "timing")]
+thread_local! {
+    static TIMINGS: Cell<Timings> = Cell::default();
+}
+
+/// Run `f`, adding the time it took to `phase` on this thread.
+#[inline]
+pub(crate) fn timed<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "timing")]
+    {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        TIMINGS.with(|timings| {
+            let mut t = timings.get();
+            match phase {
+                Phase::Hash => t.hash += elapsed,
+                Phase::Copy => t.copy += elapsed,
+                Phase::Metadata => t.metadata += elapsed,
+            }
+            timings.set(t);
+        });
+        result
+    }
+    #[cfg(not(feature = "timing"))]
+    {
+        let _ = phase;
+        f()
+    }
+}
+
+#[cfg(feature = "timing")]
+impl Timings {
+    /// The time measured on this thread so far.
+    pub(crate) fn current() -> Self {
+        TIMINGS.with(Cell::get)
+    }
+
+    /// The time measured between `before` and `self`.
+    pub(crate) fn since(self, before: Self) -> Self {
+        Self {
+            hash: self.hash - before.hash,
+            copy: self.copy - before.copy,
+            metadata: self.metadata - before.metadata,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    hash::Hasher,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::{error::Context, hash_reader, Operation, Side, VerifyReport};
+
+/// Check that the tree at `dir` holds exactly the files of `manifest`, with
+/// the content hashing to what it records, without any source to diff with.
+///
+/// `manifest` maps paths relative to `dir` to hashes from the same hasher,
+/// e.g. gathered with [`DiffOptions::on_hash`](crate::DiffOptions::on_hash).
+/// Nothing is written.
+pub fn verify_against_manifest<H: Hasher + Default>(
+    dir: impl AsRef<Path>,
+    manifest: &BTreeMap<PathBuf, u64>,
+) -> io::Result<VerifyReport> {
+    let dir = dir.as_ref();
+    let mut files = BTreeSet::new();
+    collect(dir, Path::new(""), &mut files)?;
+    let mut report = VerifyReport::default();
+    for (rel, &hash) in manifest {
+        if !files.remove(rel) {
+            report.missing.push(rel.clone());
+            continue;
+        }
+        let path = dir.join(rel);
+        let file = File::open(&path).context(Operation::Read, Side::Destination, &path)?;
+        let file_hash = hash_reader::<H>(BufReader::new(file)).context(
+            Operation::Read,
+            Side::Destination,
+            &path,
+        )?;
+        if file_hash != hash {
+            report.mismatched.push(rel.clone());
+        }
+    }
+    report.extra = files.into_iter().collect();
+    Ok(report)
+}
+
+/// Add the path relative to the root of every file under `dir`, at `rel`.
+pub(crate) fn collect(dir: &Path, rel: &Path, files: &mut BTreeSet<PathBuf>) -> io::Result<()> {
+    // Listed in full first, not to hold a handle per level of the tree.
+    let entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .context(Operation::ReadDir, Side::Destination, dir)?;
+    for entry in entries {
+        let path = entry.path();
+        let rel = rel.join(entry.file_name());
+        if path.is_dir() {
+            collect(&path, &rel, files)?;
+        } else {
+            files.insert(rel);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_verify_against_manifest() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub/ok.txt"), "ok")?;
+        fs::write(dir.path().join("bad.txt"), "tampered")?;
+        fs::write(dir.path().join("extra.txt"), "extra")?;
+        let hash = |content: &[u8]| hash_reader::<FxHasher>(content);
+        let manifest = BTreeMap::from([
+            (PathBuf::from("sub/ok.txt"), hash(b"ok")?),
+            (PathBuf::from("bad.txt"), hash(b"bad")?),
+            (PathBuf::from("gone.txt"), hash(b"gone")?),
+        ]);
+
+        let report = verify_against_manifest::<FxHasher>(dir.path(), &manifest)?;
+
+        assert_eq!(report.mismatched, [PathBuf::from("bad.txt")]);
+        assert_eq!(report.missing, [PathBuf::from("gone.txt")]);
+        assert_eq!(report.extra, [PathBuf::from("extra.txt")]);
+        assert!(!report.is_ok());
+
+        Ok(())
+    }
+}
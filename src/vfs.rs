@@ -0,0 +1,288 @@
+//! The storage diffs run against, the local filesystem unless another
+//! [`FileSystem`] is given through
+//! [`DiffOptions::file_system`](crate::DiffOptions::file_system).
+
+use std::{
+    fs,
+    hash::Hasher,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    write_on_bytes_diff_with_options, write_on_dir_diff_with_options,
+    write_on_file_diff_with_options, DiffOptions, DirDiffStats, WriteOutcome,
+};
+
+/// What the diff needs to know about an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsMetadata {
+    pub is_dir: bool,
+    pub len: u64,
+}
+
+/// The storage operations diffs go through, to diff against something else
+/// than the local filesystem.
+///
+/// Options relying on what only the local filesystem has, such as symlinks,
+/// hardlinks, timestamps, permissions and free space, as well as journals
+/// and sidecars, still use [`std::fs`] and shouldn't be combined with
+/// another file system.
+pub trait FileSystem {
+    /// Open `path` for reading, the diff buffering it.
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+
+    /// Create or truncate `path`.
+    fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write + '_>>;
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata>;
+
+    /// The full paths of the entries of the directory `path`.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let mut writer = self.create_write(to)?;
+        let copied = io::copy(&mut self.open_read(from)?, &mut writer)?;
+        writer.flush()?;
+        Ok(copied)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove the file or the whole directory tree at `path`, e.g. when
+    /// [`mirror`](crate::DiffOptions::mirror)ing.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+}
+
+/// The local filesystem through [`std::fs`], used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(fs::File::create(path)?))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(FsMetadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        fs::copy(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    /// Symlinks are removed rather than followed.
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+}
+
+impl<'a> DiffOptions<'a> {
+    /// The storage to diff on.
+    pub(crate) fn fs(&self) -> &'a dyn FileSystem {
+        self.file_system.unwrap_or(&StdFs)
+    }
+
+    /// Whether the diff runs on the local filesystem, for what only applies
+    /// there.
+    pub(crate) fn local(&self) -> bool {
+        self.file_system.is_none()
+    }
+}
+
+/// The whole content of `path` on `fs`, like [`fs::read`].
+pub(crate) fn read(fs: &dyn FileSystem, path: &Path) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    fs.open_read(path)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// [`write_on_file_diff_with_options`] on `fs`.
+pub fn write_on_file_diff_in<H: Hasher + Default>(
+    fs: &impl FileSystem,
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> io::Result<WriteOutcome> {
+    write_on_file_diff_with_options::<H>(from, to, &mut DiffOptions::new().file_system(fs))
+}
+
+/// [`write_on_bytes_diff_with_options`] on `fs`.
+pub fn write_on_bytes_diff_in<H: Hasher + Default>(
+    fs: &impl FileSystem,
+    from: &[u8],
+    to: impl AsRef<Path>,
+) -> io::Result<WriteOutcome> {
+    write_on_bytes_diff_with_options::<H>(from, to, &mut DiffOptions::new().file_system(fs))
+}
+
+/// [`write_on_dir_diff_with_options`] on `fs`.
+pub fn write_on_dir_diff_in<H: Hasher + Default>(
+    fs: &impl FileSystem,
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> io::Result<DirDiffStats> {
+    write_on_dir_diff_with_options::<H>(from, to, &mut DiffOptions::new().file_system(fs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SkipReason;
+    use rustc_hash::FxHasher;
+    use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+    /// Files only, directories exist implicitly as path prefixes.
+    #[derive(Default)]
+    struct MemFs(Rc<RefCell<BTreeMap<PathBuf, Vec<u8>>>>);
+
+    struct MemWriter {
+        files: Rc<RefCell<BTreeMap<PathBuf, Vec<u8>>>>,
+        path: PathBuf,
+    }
+
+    impl Write for MemWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut files = self.files.borrow_mut();
+            files.get_mut(&self.path).unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl FileSystem for MemFs {
+        fn open_read(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+            let files = self.0.borrow();
+            let content = files.get(path).ok_or(io::ErrorKind::NotFound)?;
+            Ok(Box::new(io::Cursor::new(content.clone())))
+        }
+
+        fn create_write(&self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+            self.0.borrow_mut().insert(path.to_path_buf(), Vec::new());
+            Ok(Box::new(MemWriter {
+                files: self.0.clone(),
+                path: path.to_path_buf(),
+            }))
+        }
+
+        fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+            let files = self.0.borrow();
+            match files.get(path) {
+                Some(content) => Ok(FsMetadata {
+                    is_dir: false,
+                    len: content.len() as u64,
+                }),
+                None if files.keys().any(|file| file.starts_with(path)) => Ok(FsMetadata {
+                    is_dir: true,
+                    len: 0,
+                }),
+                None => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            let mut entries: Vec<_> = self
+                .0
+                .borrow()
+                .keys()
+                .filter_map(|file| file.strip_prefix(path).ok())
+                .filter_map(|rel| rel.iter().next())
+                .map(|name| path.join(name))
+                .collect();
+            entries.dedup();
+            Ok(entries)
+        }
+
+        fn create_dir_all(&self, _: &Path) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            self.0
+                .borrow_mut()
+                .retain(|file, _| !file.starts_with(path));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dir_diff_in_memory() -> io::Result<()> {
+        let fs = MemFs::default();
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"same", "from/same.txt")?;
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"new", "from/sub/changed.txt")?;
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"same", "to/same.txt")?;
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"old", "to/sub/changed.txt")?;
+
+        let stats = write_on_dir_diff_in::<FxHasher>(&fs, "from", "to")?;
+
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.overwritten, 1);
+        assert_eq!(fs.0.borrow()[Path::new("to/sub/changed.txt")], b"new");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_std_fs_matches_plain_functions() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.txt");
+
+        assert_eq!(
+            write_on_bytes_diff_in::<FxHasher>(&StdFs, b"hello", &path)?,
+            WriteOutcome::Created
+        );
+        assert_eq!(
+            write_on_bytes_diff_in::<FxHasher>(&StdFs, b"hello", &path)?,
+            WriteOutcome::Skipped(SkipReason::SizeAndHashMatch)
+        );
+        assert_eq!(fs::read_to_string(&path)?, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_diff_in_memory_mirror() -> io::Result<()> {
+        let fs = MemFs::default();
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"kept", "from/kept.txt")?;
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"kept", "to/kept.txt")?;
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"extra", "to/extra.txt")?;
+        write_on_bytes_diff_in::<FxHasher>(&fs, b"extra", "to/old/extra.txt")?;
+
+        let mut options = DiffOptions::new().file_system(&fs).mirror(true);
+        let stats = write_on_dir_diff_with_options::<FxHasher>("from", "to", &mut options)?;
+
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(stats.deleted.len(), 2);
+        let files: Vec<_> = fs.0.borrow().keys().cloned().collect();
+        assert_eq!(
+            files,
+            [PathBuf::from("from/kept.txt"), PathBuf::from("to/kept.txt")]
+        );
+
+        Ok(())
+    }
+}
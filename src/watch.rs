@@ -0,0 +1,157 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    hash::Hasher,
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::{
+    write_on_dir_diff_with_options, write_on_paths_diff_with_options, DiffOptions, DirDiffStats,
+};
+
+/// How long events must stop coming before a burst of them is synced.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// A running [`watch_dir_diff`], stopped when dropped.
+pub struct WatchHandle {
+    watcher: Option<notify::RecommendedWatcher>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchHandle {
+    /// Stop watching, waiting for a sync in progress to finish.
+    pub fn stop(mut self) {
+        self.watcher = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        // Ends the sync thread once it's done with the events so far.
+        self.watcher = None;
+    }
+}
+
+/// Keep `to` in sync with `from`: run a full
+/// [`write_on_dir_diff_with_options`] first, then
+/// [`write_on_paths_diff_with_options`] for the paths changed as filesystem
+/// events come, until the returned handle is stopped.
+///
+/// Syncs run on a thread of their own, with options from `options` for each
+/// of them, and `on_sync` gets their results. Events are debounced, so that
+/// a burst of writes such as a build output is synced in one go. A full diff
+/// is run again when the watcher loses track of events or directories
+/// change.
+pub fn watch_dir_diff<H: Hasher + Default + 'static>(
+    from: impl Into<PathBuf>,
+    to: impl Into<PathBuf>,
+    mut options: impl FnMut() -> DiffOptions<'static> + Send + 'static,
+    mut on_sync: impl FnMut(io::Result<DirDiffStats>) + Send + 'static,
+) -> io::Result<WatchHandle> {
+    let from = fs::canonicalize(from.into())?;
+    let to = to.into();
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    watcher
+        .watch(&from, RecursiveMode::Recursive)
+        .map_err(io::Error::other)?;
+    let thread = thread::spawn(move || {
+        on_sync(write_on_dir_diff_with_options::<H>(
+            &from,
+            &to,
+            &mut options(),
+        ));
+        // Writes to a destination inside the source aren't changes to sync.
+        let skip = fs::canonicalize(&to).ok();
+        while let Ok(event) = events.recv() {
+            let mut paths = BTreeSet::new();
+            let mut rescan = false;
+            let mut next = Ok(event);
+            loop {
+                match next {
+                    Ok(Ok(event)) if !matches!(event.kind, EventKind::Access(_)) => {
+                        rescan |= event.need_rescan();
+                        paths.extend(event.paths.into_iter().filter(|path| {
+                            skip.as_deref().is_none_or(|skip| !path.starts_with(skip))
+                        }));
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(_)) => rescan = true,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                next = events.recv_timeout(DEBOUNCE);
+            }
+            let paths = relative(&from, paths);
+            if paths.is_empty() && !rescan {
+                continue;
+            }
+            rescan |= paths.iter().any(|rel| from.join(rel).is_dir());
+            let synced = if rescan {
+                write_on_dir_diff_with_options::<H>(&from, &to, &mut options())
+            } else {
+                write_on_paths_diff_with_options::<H>(&from, &to, &paths, &mut options())
+            };
+            on_sync(synced);
+        }
+    });
+    Ok(WatchHandle {
+        watcher: Some(watcher),
+        thread: Some(thread),
+    })
+}
+
+/// The `paths` below `root` relative to it, leaving out `root` itself.
+fn relative(root: &Path, paths: BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(root).ok())
+        .filter(|rel| !rel.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHasher;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_watch_dir_diff() -> io::Result<()> {
+        let from_dir = tempdir()?;
+        fs::write(from_dir.path().join("a.txt"), "a")?;
+        let to_dir = tempdir()?;
+
+        let (sender, synced) = mpsc::channel();
+        let handle = watch_dir_diff::<FxHasher>(
+            from_dir.path(),
+            to_dir.path(),
+            DiffOptions::new,
+            move |stats| sender.send(stats.map(|stats| stats.created)).unwrap(),
+        )?;
+        let timeout = Duration::from_secs(10);
+        assert_eq!(synced.recv_timeout(timeout).unwrap()?, 1);
+
+        fs::write(from_dir.path().join("b.txt"), "b")?;
+        let mut created = 0;
+        while !to_dir.path().join("b.txt").exists() {
+            created += synced.recv_timeout(timeout).unwrap()?;
+        }
+        handle.stop();
+
+        assert_eq!(created, 1);
+        assert_eq!(fs::read(to_dir.path().join("b.txt"))?, b"b");
+
+        Ok(())
+    }
+}